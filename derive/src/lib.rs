@@ -0,0 +1,100 @@
+//! Derive macros for `async_state_machine_example`
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::visit_mut::VisitMut;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Ident, Index, ItemFn};
+
+/// Derive `AsyncSerialize` for a struct, serializing each field in declaration order
+#[proc_macro_derive(AsyncSerialize)]
+pub fn derive_async_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let Data::Struct(data) = &input.data else {
+        let message = "AsyncSerialize can only be derived for structs";
+        return syn::Error::new_spanned(&input.ident, message).to_compile_error().into();
+    };
+
+    let fields: Vec<_> = data
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(#index)
+            }
+        })
+        .collect();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let krate = quote!(::async_state_machine_example);
+    quote! {
+        impl #impl_generics #krate::AsyncSerialize for #name #ty_generics #where_clause {
+            fn serialized_len(&self) -> usize {
+                0 #(+ #krate::AsyncSerialize::serialized_len(&self.#fields))*
+            }
+
+            async fn serialize(&self, sink: &mut impl #krate::ByteSink) {
+                #(#krate::AsyncSerialize::serialize(&self.#fields, sink).await;)*
+            }
+        }
+    }
+    .into()
+}
+
+/// Count the polls spent at each `.await` of an async fn
+///
+/// `#[instrument_machine(STATS)]` declares a static `STATS: AwaitStats<N>` alongside the
+/// function, with the same visibility, and wraps each of the function's `N` await points to count
+/// its polls there. Await points in async blocks and closures within the function are counted,
+/// but not those in nested items.
+#[proc_macro_attribute]
+pub fn instrument_machine(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let stats = parse_macro_input!(attr as Ident);
+    let mut func = parse_macro_input!(item as ItemFn);
+    if func.sig.asyncness.is_none() {
+        return syn::Error::new_spanned(&func.sig, "instrument_machine requires an async fn")
+            .to_compile_error()
+            .into();
+    }
+
+    let mut visitor = AwaitVisitor { stats: &stats, labels: Vec::new() };
+    visitor.visit_block_mut(&mut func.block);
+    let labels = visitor.labels;
+    let count = labels.len();
+    let vis = &func.vis;
+    quote! {
+        #vis static #stats: ::async_state_machine_example::AwaitStats<#count> =
+            ::async_state_machine_example::AwaitStats::new([#(#labels),*]);
+
+        #func
+    }
+    .into()
+}
+
+struct AwaitVisitor<'a> {
+    stats: &'a Ident,
+    labels: Vec<String>,
+}
+
+impl VisitMut for AwaitVisitor<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        // Instrument any awaits within the awaited expression first
+        syn::visit_mut::visit_expr_mut(self, expr);
+        if let Expr::Await(await_expr) = expr {
+            let base = &await_expr.base;
+            let index = self.labels.len();
+            self.labels.push(quote!(#base).to_string());
+            let stats = self.stats;
+            *expr = syn::parse_quote! {
+                ::async_state_machine_example::instrument(#base, #stats.counter(#index)).await
+            };
+        }
+    }
+
+    fn visit_item_mut(&mut self, _item: &mut syn::Item) {
+        // Nested items are separate functions
+    }
+}
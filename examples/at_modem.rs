@@ -0,0 +1,177 @@
+use std::{cell::RefCell, collections::VecDeque, pin::pin, time::Duration};
+
+use async_state_machine_example::{
+    expect, timeout, AsyncStateMachine, Clock, LineBuffer, LineTooLong, MockClock, TimedOut,
+};
+
+/// A fake modem on the other end of a UART
+///
+/// Commands written to it are answered in full after a fixed latency. Unknown commands are never
+/// answered, so that the driver's timeout handling gets exercised.
+#[derive(Default)]
+struct MockModem {
+    cmd: Vec<u8>,
+    pending_reply: Option<(Duration, &'static [u8])>,
+    rx: VecDeque<u8>,
+}
+
+impl MockModem {
+    const LATENCY: Duration = Duration::from_millis(20);
+
+    /// Called by the driver to transmit bytes to the modem
+    fn write(&mut self, bytes: &[u8], now: Duration) {
+        for &b in bytes {
+            if b == b'\r' {
+                let reply: Option<&'static [u8]> = match self.cmd.as_slice() {
+                    b"AT" | b"ATE0" => Some(b"\r\nOK\r\n"),
+                    b"AT+CSQ" => Some(b"\r\n+CSQ: 21,0\r\n\r\nOK\r\n"),
+                    b"AT+CPIN?" => Some(b"\r\n+CME ERROR: 10\r\n"),
+                    _ => None,
+                };
+                self.pending_reply = reply.map(|r| (now + Self::LATENCY, r));
+                self.cmd.clear();
+            } else {
+                self.cmd.push(b);
+            }
+        }
+    }
+
+    /// Called by the driver to receive a byte from the modem, if one is available
+    fn read(&mut self) -> Option<u8> {
+        self.rx.pop_front()
+    }
+
+    /// Simulate the modem's own processing
+    fn step(&mut self, now: Duration) {
+        if let Some((at, reply)) = self.pending_reply
+            && now >= at
+        {
+            self.rx.extend(reply);
+            self.pending_reply = None;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ModemError {
+    Timeout,
+    LineTooLong,
+    /// The modem responded with `ERROR` or `+CME ERROR: <code>`
+    Error(Option<u32>),
+}
+
+impl From<TimedOut> for ModemError {
+    fn from(_: TimedOut) -> Self {
+        ModemError::Timeout
+    }
+}
+
+impl From<LineTooLong> for ModemError {
+    fn from(_: LineTooLong) -> Self {
+        ModemError::LineTooLong
+    }
+}
+
+/// An AT command driver, borrowing the UART and the clock
+struct Modem<'a, C: Clock> {
+    uart: &'a RefCell<MockModem>,
+    clock: &'a C,
+    line: LineBuffer<64>,
+}
+
+impl<'a, C: Clock> Modem<'a, C> {
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn new(uart: &'a RefCell<MockModem>, clock: &'a C) -> Self {
+        Self { uart, clock, line: LineBuffer::new() }
+    }
+
+    /// Send a command, and wait for its final result code
+    ///
+    /// `on_info` is passed each information line received before the result code
+    async fn command(
+        &mut self,
+        cmd: &str,
+        mut on_info: impl FnMut(&[u8]),
+    ) -> Result<(), ModemError> {
+        let uart = self.uart;
+        uart.borrow_mut().write(cmd.as_bytes(), self.clock.now());
+        uart.borrow_mut().write(b"\r", self.clock.now());
+
+        let response = expect(|| uart.borrow_mut().read(), &mut self.line, |line| match line {
+            b"OK" => Some(Ok(())),
+            b"ERROR" => Some(Err(ModemError::Error(None))),
+            _ if line.starts_with(b"+CME ERROR: ") => {
+                let code = std::str::from_utf8(&line[12..]).ok().and_then(|s| s.parse().ok());
+                Some(Err(ModemError::Error(code)))
+            }
+            b"" => None,
+            _ => {
+                on_info(line);
+                None
+            }
+        });
+        timeout(Self::TIMEOUT, self.clock, response).await??
+    }
+
+    /// Query the received signal strength indication
+    async fn signal_quality(&mut self) -> Result<u32, ModemError> {
+        let mut rssi = None;
+        self.command("AT+CSQ", |line| {
+            if let Some(value) = line.strip_prefix(b"+CSQ: ") {
+                rssi = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|s| s.split(',').next())
+                    .and_then(|s| s.parse().ok());
+            }
+        })
+        .await?;
+        rssi.ok_or(ModemError::Error(None))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Report {
+    rssi: u32,
+    sim: Result<(), ModemError>,
+    unsupported: Result<(), ModemError>,
+}
+
+/// The modem bring-up sequence, written linearly
+async fn bring_up<C: Clock>(mut modem: Modem<'_, C>) -> Result<Report, ModemError> {
+    modem.command("AT", |_| ()).await?;
+    // Disable echo
+    modem.command("ATE0", |_| ()).await?;
+    let rssi = modem.signal_quality().await?;
+    let sim = modem.command("AT+CPIN?", |_| ()).await;
+    let unsupported = modem.command("AT+BOGUS", |_| ()).await;
+    Ok(Report { rssi, sim, unsupported })
+}
+
+fn main() {
+    let clock = MockClock::new();
+    let uart = RefCell::new(MockModem::default());
+
+    let fut = pin!(bring_up(Modem::new(&uart, &clock)));
+    let mut fsm = AsyncStateMachine::new(fut);
+
+    // The main loop, running at 1kHz. The modem driver gets one poll per tick, and the mock
+    // modem gets to do its processing between polls.
+    let report = loop {
+        if let Some(result) = fsm.exec() {
+            break result;
+        }
+        clock.advance(Duration::from_millis(1));
+        uart.borrow_mut().step(clock.now());
+    };
+
+    assert_eq!(
+        report,
+        Ok(Report {
+            rssi: 21,
+            sim: Err(ModemError::Error(Some(10))),
+            unsupported: Err(ModemError::Timeout),
+        })
+    );
+    println!("{report:?} after {:?}", clock.now());
+}
@@ -1,8 +1,8 @@
 use std::{pin::Pin, sync::Arc, time::{Duration, Instant}};
 
-use async_state_machine_example::poll_once;
-use crossbeam::atomic::AtomicCell;
-use futures::pending;
+use async_state_machine_example::{
+    on_exit, poll_once, yield_now, AtomicFlag, AtomicOption, StdClock, Ticker,
+};
 
 
 
@@ -19,9 +19,9 @@ impl Database {
 
 #[derive(Default, Debug)]
 struct SharedData {
-    init_cmd: AtomicCell<bool>,
-    running: AtomicCell<bool>,
-    sensor_data: AtomicCell<Option<u32>>,
+    init_cmd: AtomicFlag,
+    running: AtomicFlag,
+    sensor_data: AtomicOption<u32>,
 }
 
 
@@ -53,7 +53,7 @@ impl CallbackDrivenModule {
     }
 
     pub fn running(&self) -> bool {
-        self.data.running.load()
+        self.data.running.get()
     }
 }
 
@@ -66,7 +66,7 @@ async fn do_init() {
             break;
         }
         // Yield back execution
-        pending!()
+        yield_now().await
     }
 }
 
@@ -74,8 +74,8 @@ async fn do_init() {
 async fn task(data: Arc<SharedData>) {
 
     // Wait for init flag to start up
-    while !data.init_cmd.load() {
-        pending!()
+    while !data.init_cmd.get() {
+        yield_now().await
     }
 
     // Simulated initialization task
@@ -83,18 +83,15 @@ async fn task(data: Arc<SharedData>) {
     do_init().await;
 
     data.running.store(true);
+    // Clear the running flag however the task ends, including if the module is dropped
+    on_exit!(|| data.running.store(false));
 
     const SENSOR_PERIOD: Duration = Duration::from_millis(100);
-    let mut next_data_time = Instant::now() + SENSOR_PERIOD;
-    while data.init_cmd.load() {
-        if Instant::now() >= next_data_time {
-            next_data_time += SENSOR_PERIOD;
-            data.sensor_data.store(Some(42));
-        }
-        pending!()
+    let mut ticker = Ticker::every(SENSOR_PERIOD, StdClock::new());
+    while data.init_cmd.get() {
+        ticker.next().await;
+        data.sensor_data.store(Some(42));
     }
-
-    data.running.store(false);
 }
 
 
@@ -106,20 +103,20 @@ fn main() {
     // Run once
     module.run(&mut db);
     assert_eq!(db.value, 0);
-    assert_eq!(module.running(), false);
+    assert!(!module.running());
 
     // Set flag to enable module
     module.enable(true);
 
     module.run(&mut db);
     // Still initializing
-    assert_eq!(module.running(), false);
+    assert!(!module.running());
 
     std::thread::sleep(Duration::from_secs(2));
 
     module.run(&mut db);
     // Now the init process should have completed
-    assert_eq!(module.running(), true);
+    assert!(module.running());
     std::thread::sleep(Duration::from_millis(200));
     module.run(&mut db);
     assert_eq!(db.value, 42);
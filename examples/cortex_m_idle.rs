@@ -0,0 +1,58 @@
+//! Sleeping a Cortex-M core on WFE until a button interrupt wakes the application
+//!
+//! The application waits for button presses on an [`EdgeSignal`], which the pin's interrupt
+//! handler triggers. [`block_on`] with [`WfeSev`] polls it once, then sleeps the core until the
+//! interrupt arrives, rather than polling it in a busy loop. This is only a compile check of the
+//! pieces, since a real application also needs a runtime crate such as `cortex-m-rt` for its
+//! vector table, and a critical-section implementation, e.g. `cortex-m`'s
+//! `critical-section-single-core` feature:
+//!
+//! ```text
+//! cargo check --example cortex_m_idle --target thumbv7em-none-eabi \
+//!     --no-default-features --features cortex-m
+//! ```
+//!
+//! On other targets the example does nothing.
+
+#![cfg_attr(target_os = "none", no_std, no_main)]
+
+#[cfg(target_os = "none")]
+mod app {
+    use core::convert::Infallible;
+    use core::pin::pin;
+
+    use async_state_machine_example::{block_on, Edge, EdgeSignal, WfeSev};
+
+    static BUTTON: EdgeSignal = EdgeSignal::new();
+
+    async fn application() -> Infallible {
+        let mut presses = 0u32;
+        loop {
+            BUTTON.wait_for_edge(Edge::Falling).await;
+            presses = presses.wrapping_add(1);
+        }
+    }
+
+    #[unsafe(no_mangle)]
+    pub extern "C" fn main() -> ! {
+        let fut = pin!(application());
+        match block_on(fut, &mut WfeSev) {}
+    }
+
+    /// The button pin's interrupt handler
+    #[unsafe(no_mangle)]
+    pub extern "C" fn EXTI0() {
+        // The wake executes SEV, and returning from the interrupt also ends the WFE
+        BUTTON.trigger(Edge::Falling);
+    }
+
+    #[panic_handler]
+    fn panic(_: &core::panic::PanicInfo) -> ! {
+        loop {
+            cortex_m::asm::wfe();
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+fn main() {}
@@ -0,0 +1,130 @@
+//! Scanning a 4x4 keypad matrix, with a debouncing coroutine per key
+//!
+//! A scanner task drives each column of the matrix in turn from a [`Ticker`], and publishes a
+//! snapshot of every key's raw contact state. Sixteen identical key machines, run by an
+//! [`ArrayExecutor`], each debounce one bit of the snapshot with [`watch_debounced`] and push
+//! press and release events to an [`EventQueue`]. The application reads the queue to collect a
+//! code terminated by `#`.
+
+use std::{cell::Cell, pin::pin, time::Duration};
+
+use async_state_machine_example::{
+    poll_once, watch_debounced, ArrayExecutor, AsyncStateMachine, Clock, EventQueue, Mailbox,
+    MockClock, Ticker,
+};
+
+const KEYS: [char; 16] = [
+    '1', '2', '3', 'A', //
+    '4', '5', '6', 'B', //
+    '7', '8', '9', 'C', //
+    '*', '0', '#', 'D',
+];
+
+const SCAN_PERIOD: Duration = Duration::from_millis(1);
+const DEBOUNCE: Duration = Duration::from_millis(5);
+
+/// A fake keypad, replaying a script of key presses with contact bounce
+struct MockKeypad {
+    /// Each key's index, the time it is pressed and the time it is released
+    presses: Vec<(usize, Duration, Duration)>,
+}
+
+impl MockKeypad {
+    const BOUNCE: Duration = Duration::from_millis(3);
+
+    fn contact(&self, key: usize, now: Duration) -> bool {
+        self.presses.iter().any(|&(k, down, up)| {
+            let bouncing = |edge: Duration| now >= edge && now < edge + Self::BOUNCE;
+            if k != key {
+                false
+            } else if bouncing(down) || bouncing(up) {
+                // The contact chatters every millisecond around each edge
+                now.as_millis().is_multiple_of(2)
+            } else {
+                now >= down && now < up
+            }
+        })
+    }
+
+    /// Drive column `col` and read back the rows, one bit per row
+    fn read_rows(&self, col: usize, now: Duration) -> u8 {
+        (0..4).filter(|row| self.contact(row * 4 + col, now)).map(|row| 1 << row).sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeyEvent {
+    key: char,
+    pressed: bool,
+}
+
+/// Scan the whole matrix once per period, publishing one bit per key
+async fn scanner(keypad: &MockKeypad, clock: &MockClock, snapshot: &Cell<u16>) {
+    let mut ticker = Ticker::every(SCAN_PERIOD, clock);
+    loop {
+        ticker.next().await;
+        let mut bits = 0;
+        for col in 0..4 {
+            let rows = keypad.read_rows(col, clock.now());
+            for row in 0..4 {
+                if rows & (1 << row) != 0 {
+                    bits |= 1 << (row * 4 + col);
+                }
+            }
+        }
+        snapshot.set(bits);
+    }
+}
+
+/// Collect the keys pressed up to a `#`
+async fn read_code(events: &EventQueue<KeyEvent, 8>) -> String {
+    let mut code = String::new();
+    loop {
+        let event = events.recv().await;
+        println!("{:?}", event);
+        match event {
+            KeyEvent { key: '#', pressed: true } => return code,
+            KeyEvent { key, pressed: true } => code.push(key),
+            KeyEvent { pressed: false, .. } => (),
+        }
+    }
+}
+
+fn main() {
+    let ms = Duration::from_millis;
+    let keypad = MockKeypad {
+        presses: vec![
+            (4, ms(10), ms(60)),
+            // Too short to survive debouncing
+            (5, ms(80), ms(82)),
+            (1, ms(100), ms(130)),
+            (14, ms(150), ms(200)),
+        ],
+    };
+    let clock = MockClock::new();
+    let snapshot = Cell::new(0);
+    let events = EventQueue::new();
+
+    let mut scan = pin!(scanner(&keypad, &clock, &snapshot));
+    let inboxes: [Mailbox<()>; 16] = Default::default();
+    let mut keys = pin!(ArrayExecutor::new(&inboxes, |key, _| {
+        let (snapshot, clock, events) = (&snapshot, &clock, &events);
+        watch_debounced(move || snapshot.get() & (1 << key) != 0, DEBOUNCE, clock, move |pressed| {
+            events.push(KeyEvent { key: KEYS[key], pressed }).ok();
+        })
+    }));
+    let app = pin!(read_code(&events));
+    let mut app = AsyncStateMachine::new(app);
+
+    let code = loop {
+        poll_once(scan.as_mut());
+        keys.as_mut().tick();
+        if let Some(code) = app.exec() {
+            break code;
+        }
+        clock.advance(SCAN_PERIOD);
+    };
+
+    assert_eq!(code, "42");
+    println!("Entered code {} at {:?}", code, clock.now());
+}
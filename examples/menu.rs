@@ -0,0 +1,98 @@
+//! A settings menu written as a coroutine, stepped once per display refresh
+//!
+//! The whole UI flow is one async fn: the main menu, the value editors it opens and the about
+//! screen are ordinary nested calls, and the position in the flow is just where the body is
+//! suspended. The display loop resumes the flow's [`Coroutine`] once per refresh and redraws
+//! whenever a new [`Screen`] is yielded, while button presses arrive through an [`EventQueue`].
+
+use std::pin::pin;
+
+use async_state_machine_example::{
+    edit_value, menu, show, Button, Coroutine, CoroutineState, EventQueue, Screen, Yielder,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Settings {
+    brightness: i32,
+    contrast: i32,
+}
+
+/// The whole UI: returns the settings once the user backs out of the main menu
+async fn settings_ui<'a>(
+    yielder: &Yielder<Screen<'a>>,
+    buttons: &EventQueue<Button, 4>,
+    mut settings: Settings,
+) -> Settings {
+    const ITEMS: &[&str] = &["Brightness", "Contrast", "About"];
+    loop {
+        match menu(yielder, "Settings", ITEMS, buttons).await {
+            Some(item @ 0..=1) => {
+                let setting = match item {
+                    0 => &mut settings.brightness,
+                    _ => &mut settings.contrast,
+                };
+                let edited = edit_value(yielder, ITEMS[item], *setting, 0..=10, buttons);
+                if let Some(value) = edited.await {
+                    *setting = value;
+                }
+            }
+            Some(_) => {
+                show(yielder, Screen::Message("Demo v1.0"), buttons).await;
+            }
+            None => return settings,
+        }
+    }
+}
+
+/// Draw a screen as text, as a stand-in for a real display driver
+fn render(screen: &Screen) -> String {
+    match screen {
+        Screen::Menu { title, items, selected } => {
+            let items: Vec<_> = items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| match i == *selected {
+                    true => format!("[{}]", item),
+                    false => item.to_string(),
+                })
+                .collect();
+            format!("{}: {}", title, items.join(" "))
+        }
+        Screen::Value { label, value } => format!("{}: < {} >", label, value),
+        Screen::Message(text) => text.to_string(),
+    }
+}
+
+fn main() {
+    use Button::*;
+    // The button pressed before each refresh, if any
+    let script = [
+        // Raise the contrast to 7
+        None, Some(Down), None, Some(Select), Some(Up), Some(Up), Some(Select),
+        // Look at the about screen
+        Some(Down), Some(Down), Some(Select), Some(Back), None,
+        // Start editing the brightness, then change our mind, and leave
+        Some(Select), Some(Down), Some(Back), Some(Back),
+    ];
+
+    let yielder = Yielder::new();
+    let buttons = EventQueue::new();
+    let initial = Settings { brightness: 5, contrast: 5 };
+    let body = pin!(settings_ui(&yielder, &buttons, initial));
+    let mut ui = Coroutine::new(body, &yielder);
+
+    let mut refreshes = script.iter();
+    let settings = loop {
+        if let Some(Some(button)) = refreshes.next() {
+            buttons.push(*button).unwrap();
+        }
+        match ui.resume() {
+            CoroutineState::Yielded(screen) => println!("{}", render(&screen)),
+            CoroutineState::Pending => (),
+            CoroutineState::Complete(settings) => break settings,
+        }
+    };
+
+    assert_eq!(settings, Settings { brightness: 5, contrast: 7 });
+    println!("Saved {:?}", settings);
+}
@@ -1,5 +1,12 @@
+use std::{cell::RefCell, collections::VecDeque, pin::pin};
+
+use async_state_machine_example::{
+    poll_once, write_frame, AsyncSerialize, InvalidState, PersistentStateMachine, PingPong,
+};
+
 /// Just an example of an object to be serialized. Each object has a type, and some arbitrary block
 /// of bytes to describe it.
+#[derive(AsyncSerialize, Debug, PartialEq)]
 struct Object {
     pub object_type: u8,
     pub data: Vec<u8>,
@@ -8,13 +15,15 @@ struct Object {
 mod async_serialize {
     use super::Object;
     use std::{cell::RefCell, pin::{pin, Pin}};
-    use async_state_machine_example::poll_once;
-    use futures::pending;
+    use async_state_machine_example::{
+        poll_once, write_frame, write_text, AsyncSerialize, ByteSink, YieldingSink,
+    };
 
 
     struct AsyncSerializer<'a, 'b, F: Future<Output = ()>> {
         fut: Pin<&'a mut F>,
         reg: &'b RefCell<u8>,
+        finished: bool,
     }
 
     impl<'a, 'b, F> AsyncSerializer<'a, 'b, F>
@@ -25,7 +34,7 @@ mod async_serialize {
         ///
         /// The future should write to reg, and it *must yield after writing each byte*.
         pub fn new(fut: Pin<&'a mut F>, reg: &'b RefCell<u8>) -> Self {
-            Self { fut, reg }
+            Self { fut, reg, finished: false }
         }
     }
 
@@ -33,12 +42,13 @@ mod async_serialize {
         fn read(&mut self, buf: &mut [u8]) -> usize {
             let mut pos = 0;
             loop {
-                if pos >= buf.len() {
+                if pos >= buf.len() || self.finished {
                     return pos;
                 }
 
-                if let Some(_) = poll_once(self.fut.as_mut()) {
-                    // Serialization is complete
+                if poll_once(self.fut.as_mut()).is_some() {
+                    // Serialization is complete. The future must not be polled again.
+                    self.finished = true;
                     return pos;
                 } else {
                     buf[pos] = *self.reg.borrow();
@@ -46,53 +56,211 @@ mod async_serialize {
                 }
             }
         }
+
+        fn skip(&mut self, n: usize) -> usize {
+            // Run the machine forward without reading its output
+            let mut skipped = 0;
+            while skipped < n && !self.finished {
+                if poll_once(self.fut.as_mut()).is_some() {
+                    self.finished = true;
+                } else {
+                    skipped += 1;
+                }
+            }
+            skipped
+        }
     }
 
     /// Create a serializer for objects, and pass it to the provided callback
     ///
     /// This allows for pinning the required data on the stack for the duration of the serializer
-    /// lifetime
-    pub fn serialize_objects(objects: &[Object], mut cb: impl FnMut(&mut dyn PersistSerializer)) {
+    /// lifetime. Whatever the callback returns is passed back to the caller.
+    pub fn serialize_objects<'o, T: AsyncSerialize + 'o, R>(
+        objects: impl IntoIterator<Item = &'o T>,
+        cb: impl FnOnce(&mut dyn PersistSerializer) -> R,
+    ) -> R {
+        let reg = RefCell::new(0u8);
+        let fut = pin!(polling_serializer(objects, |b| *reg.borrow_mut() = b));
+        let mut serializer = AsyncSerializer::new(fut, &reg);
+        cb(&mut serializer)
+    }
+
+    /// Create a serializer writing objects as CSV text, one line per object
+    ///
+    /// Each line has the object type, the data length, and then the data bytes, all in decimal.
+    pub fn serialize_csv<'o, R>(
+        objects: impl IntoIterator<Item = &'o Object>,
+        cb: impl FnOnce(&mut dyn PersistSerializer) -> R,
+    ) -> R {
         let reg = RefCell::new(0u8);
-        let fut = pin!(polling_serializer(objects, |b| *(&reg).borrow_mut() = b));
+        let fut = pin!(csv_serializer(objects, |b| *reg.borrow_mut() = b));
         let mut serializer = AsyncSerializer::new(fut, &reg);
-        cb(&mut serializer);
+        cb(&mut serializer)
+    }
+
+    /// Error returned by [`PersistSerializer::read_exact`] when the stream ends first
+    #[derive(Debug, PartialEq)]
+    pub struct Incomplete {
+        /// The number of bytes which were read before the end of the stream
+        pub read: usize,
+    }
+
+    impl std::fmt::Display for Incomplete {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "stream ended after {} bytes", self.read)
+        }
     }
 
     pub trait PersistSerializer {
+        /// Read the next bytes of the stream into `buf`
+        ///
+        /// Returns the number of bytes read, which is less than `buf.len()` only once the stream
+        /// has ended.
         fn read(&mut self, buf: &mut [u8]) -> usize;
-    }
 
+        /// Fill `buf` completely, or report how much was read before the stream ended
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Incomplete> {
+            let mut pos = 0;
+            while pos < buf.len() {
+                match self.read(&mut buf[pos..]) {
+                    0 => return Err(Incomplete { read: pos }),
+                    n => pos += n,
+                }
+            }
+            Ok(())
+        }
+
+        /// Fill each of `bufs` in turn, e.g. to drain straight into scatter-gather buffers
+        ///
+        /// Returns the total number of bytes read. As for [`PersistSerializer::read`], that is
+        /// less than the total length of `bufs` only once the stream has ended.
+        fn read_vectored(&mut self, bufs: &mut [&mut [u8]]) -> usize {
+            let mut total = 0;
+            for buf in bufs {
+                let n = self.read(buf);
+                total += n;
+                if n < buf.len() {
+                    break;
+                }
+            }
+            total
+        }
+
+        /// Discard the next `n` bytes of the stream, e.g. to resume an interrupted transfer
+        ///
+        /// Returns the number of bytes skipped, which is less than `n` only if the stream ended.
+        fn skip(&mut self, n: usize) -> usize {
+            let mut skipped = 0;
+            let mut scratch = [0; 16];
+            while skipped < n {
+                let len = scratch.len().min(n - skipped);
+                let read = self.read(&mut scratch[..len]);
+                skipped += read;
+                if read < len {
+                    break;
+                }
+            }
+            skipped
+        }
 
-    /// Write bytes to a provided function, returning a Poll::Pending between each byte
-    async fn write_bytes(src: &[u8], mut write_fn: impl FnMut(u8)) {
-        for i in 0..src.len() {
-            write_fn(src[i]);
-            pending!()
+        /// Append the rest of the stream to `out`, returning the number of bytes read
+        fn read_to_end(&mut self, out: &mut Vec<u8>) -> usize {
+            let start = out.len();
+            let mut buf = [0; 64];
+            loop {
+                let n = self.read(&mut buf);
+                out.extend_from_slice(&buf[..n]);
+                if n < buf.len() {
+                    return out.len() - start;
+                }
+            }
         }
     }
 
+
     /// Implements a serializer for a list of objects.
     ///
     /// Implementing this as an async function allows the sequence of writes to be written linearly and
     /// simply, allowing rustc to compile it into a state machine so that the serialization can be
     /// broken up into arbitrary chunks
-    async fn polling_serializer(objects: &[Object], mut write_fn: impl FnMut(u8)) {
+    async fn polling_serializer<'o, T: AsyncSerialize + 'o>(
+        objects: impl IntoIterator<Item = &'o T>,
+        write_fn: impl FnMut(u8),
+    ) {
+        // The sink yields after each byte, so the future returns Poll::Pending between each one
+        let mut sink = YieldingSink::new(write_fn);
         for obj in objects {
-            // First serialize the size of the object, which is the length of the data + 1 byte for the
-            // object type
-            let len = (obj.data.len() + 1) as u16;
-            write_bytes(&len.to_le_bytes(), &mut write_fn).await;
-            // Serialize the object type
-            write_bytes(&[obj.object_type], &mut write_fn).await;
-            // Serialize the object data
-            write_bytes(&obj.data, &mut write_fn).await;
+            // Each object is prefixed with its size, which for an `Object` is the length of the
+            // data + 1 byte for the object type
+            write_frame(obj, &mut sink).await.expect("objects are short");
+        }
+    }
+
+    /// Implements the CSV serialization, formatting each field with `write_text!`
+    ///
+    /// The text is produced through the same yielding sink as the binary format, so it is drained
+    /// through the same chunked `read()`.
+    async fn csv_serializer<'o>(
+        objects: impl IntoIterator<Item = &'o Object>,
+        write_fn: impl FnMut(u8),
+    ) {
+        let mut sink = YieldingSink::new(write_fn);
+        for obj in objects {
+            // Each field is far shorter than write_text!'s buffer, so formatting can't fail
+            write_text!(&mut sink, "{},{}", obj.object_type, obj.data.len()).await.unwrap();
+            for b in &obj.data {
+                write_text!(&mut sink, ",{b}").await.unwrap();
+            }
+            sink.write(b"\n").await;
         }
     }
 }
 
-mod sync_serialize {
+mod async_deserialize {
     use super::Object;
+    use std::{cell::RefCell, collections::VecDeque, pin::Pin};
+    use async_state_machine_example::{poll_once, read_frame};
+
+    /// Reconstructs objects from the serialized stream, which can be fed in chunks of any size
+    pub struct AsyncDeserializer<'a, F: Future<Output = ()>> {
+        fut: Pin<&'a mut F>,
+        input: &'a RefCell<VecDeque<u8>>,
+    }
+
+    impl<'a, F> AsyncDeserializer<'a, F>
+    where
+        F: Future<Output = ()>
+    {
+        /// Create a deserializer from a `parser` future, and the queue it reads its input from
+        pub fn new(fut: Pin<&'a mut F>, input: &'a RefCell<VecDeque<u8>>) -> Self {
+            Self { fut, input }
+        }
+
+        /// Pass the next chunk of the stream to the parser
+        pub fn feed(&mut self, chunk: &[u8]) {
+            self.input.borrow_mut().extend(chunk);
+            // The parser consumes at least one byte per poll, until it runs out
+            while !self.input.borrow().is_empty() {
+                poll_once(self.fut.as_mut());
+            }
+        }
+    }
+
+    /// Parses objects out of the bytes read from `input`, forever
+    pub async fn parser(input: &RefCell<VecDeque<u8>>, objects: &RefCell<Vec<Object>>) {
+        let mut frame = Vec::new();
+        loop {
+            read_frame(|| input.borrow_mut().pop_front(), &mut frame).await;
+            // A frame always has at least the type byte; skip any that don't
+            if let Some((&object_type, data)) = frame.split_first() {
+                objects.borrow_mut().push(Object { object_type, data: data.to_vec() });
+            }
+        }
+    }
+}
+
+mod sync_serialize {
+    use super::{InvalidState, Object, PersistentStateMachine};
 
     pub enum State {
         Len1,
@@ -145,7 +313,7 @@ mod sync_serialize {
                     },
                     State::Type => {
                         buf[pos] = obj.object_type;
-                        if obj.data.len() > 0 {
+                        if !obj.data.is_empty() {
                             State::Data(0)
                         } else {
                             self.idx += 1;
@@ -166,6 +334,50 @@ mod sync_serialize {
             }
         }
     }
+
+    /// Because the position is explicit, it can be saved, and the serialization resumed by a new
+    /// serializer over the same objects, e.g. after a reboot part way through a transfer. The
+    /// state is the object index, and the state tag and data offset within that object, each
+    /// saved as a `u32`, so positions past `u32::MAX` objects or data bytes can't be saved.
+    impl PersistentStateMachine for SyncSerializer<'_> {
+        const STATE_LEN: usize = 9;
+
+        fn save_state(&self, buf: &mut [u8]) {
+            let (tag, offset) = match self.state {
+                State::Len1 => (0, 0),
+                State::Len2 => (1, 0),
+                State::Type => (2, 0),
+                State::Data(offset) => (3, offset),
+            };
+            let idx = u32::try_from(self.idx).expect("object index fits in a u32");
+            let offset = u32::try_from(offset).expect("data offset fits in a u32");
+            buf[0..4].copy_from_slice(&idx.to_le_bytes());
+            buf[4] = tag;
+            buf[5..9].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        fn restore(&mut self, bytes: &[u8]) -> Result<(), InvalidState> {
+            let bytes = bytes.get(..Self::STATE_LEN).ok_or(InvalidState)?;
+            let idx = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+            let offset = u32::from_le_bytes(bytes[5..9].try_into().unwrap()) as usize;
+            // A finished serializer is always saved at the start of the object after the last
+            let data_len = match self.objects.get(idx) {
+                Some(obj) => obj.data.len(),
+                None if idx == self.objects.len() && bytes[4] == 0 => 0,
+                None => return Err(InvalidState),
+            };
+            let state = match (bytes[4], offset) {
+                (0, 0) => State::Len1,
+                (1, 0) => State::Len2,
+                (2, 0) => State::Type,
+                (3, offset) if offset < data_len => State::Data(offset),
+                _ => return Err(InvalidState),
+            };
+            self.idx = idx;
+            self.state = state;
+            Ok(())
+        }
+    }
 }
 
 fn main() {
@@ -184,14 +396,16 @@ fn main() {
     // A vec to store the fully written data
     let mut async_output = Vec::new();
 
-    async_serialize::serialize_objects(&objects, |serializer| {
+    let chunks = async_serialize::serialize_objects(&objects, |serializer| {
         // A temporary small buffer. Data will be serialized one 3-byte chunk at a time into this buffer.
         let mut buf = [0; 3];
+        let mut chunks = 0;
         loop {
             let write_size = serializer.read(&mut buf);
             async_output.extend_from_slice(&buf[0..write_size]);
+            chunks += 1;
             if write_size < buf.len() {
-                break;
+                break chunks;
             }
         }
     });
@@ -210,4 +424,86 @@ fn main() {
 
     assert_eq!(async_output, [5,0,0,1,2,3,4, 9,0,1,1,2,3,4,5,6,7,8]);
     assert_eq!(sync_output, async_output);
+    assert_eq!(chunks, 7);
+
+    // Save the sync serializer's position part way through, then resume with a new one, as if
+    // the transfer had been interrupted by a reboot. The state is saved to the start of an erased
+    // flash page, and the whole page is read back.
+    let mut saved = [0xff; 64];
+    let mut sync_serializer = sync_serialize::SyncSerializer::new(&objects);
+    let mut first = [0; 10];
+    assert_eq!(sync_serializer.read(&mut first), 10);
+    sync_serializer.save_state(&mut saved);
+    let mut sync_serializer = sync_serialize::SyncSerializer::new(&objects);
+    assert_eq!(sync_serializer.restore(&[0, 0, 0, 0, 3, 9, 0, 0, 0]), Err(InvalidState));
+    assert_eq!(sync_serializer.restore(&saved[..8]), Err(InvalidState));
+    sync_serializer.restore(&saved).unwrap();
+    let mut rest = [0; 32];
+    let rest_len = sync_serializer.read(&mut rest);
+    assert_eq!(rest[..rest_len], async_output[10..]);
+
+    // Read the output back, fed in chunks of varying sizes
+    let input = RefCell::new(VecDeque::new());
+    let parsed = RefCell::new(Vec::new());
+    let fut = pin!(async_deserialize::parser(&input, &parsed));
+    let mut deserializer = async_deserialize::AsyncDeserializer::new(fut, &input);
+    let mut rest = async_output.as_slice();
+    for size in [1, 5, 0, 2, 7].into_iter().cycle() {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, tail) = rest.split_at(size.min(rest.len()));
+        deserializer.feed(chunk);
+        rest = tail;
+    }
+    assert_eq!(*parsed.borrow(), objects);
+
+    // The same serialization into a double buffer needs a poll per buffer drained, rather than
+    // one per byte through the register
+    let ping_pong = PingPong::<8>::new();
+    let mut buffered_output = Vec::new();
+    let mut fut = pin!(async {
+        for obj in &objects {
+            write_frame(obj, &mut &ping_pong).await.expect("objects are short");
+        }
+        ping_pong.flush();
+    });
+    let mut polls = 0;
+    let mut done = false;
+    while !done || ping_pong.ready().is_some() {
+        if !done {
+            done = poll_once(fut.as_mut()).is_some();
+            polls += 1;
+        }
+        if let Some(ready) = ping_pong.ready() {
+            buffered_output.extend_from_slice(&ready);
+        }
+        ping_pong.release();
+    }
+    assert_eq!(buffered_output, async_output);
+    assert_eq!(polls, 2);
+
+    // Resume a transfer which was interrupted after the first object
+    let resumed = async_serialize::serialize_objects(&objects, |serializer| {
+        assert_eq!(serializer.skip(7), 7);
+        let mut rest = Vec::new();
+        serializer.read_to_end(&mut rest);
+        assert_eq!(serializer.skip(1), 0);
+        rest
+    });
+    assert_eq!(resumed, async_output[7..]);
+
+    // The same objects as CSV text, read out through the same interface
+    let (header, csv) = async_serialize::serialize_csv(&objects, |serializer| {
+        let mut header = [0; 4];
+        let (first, second) = header.split_at_mut(1);
+        assert_eq!(serializer.read_vectored(&mut [first, second]), 4);
+        let mut csv = Vec::new();
+        serializer.read_to_end(&mut csv);
+        let end = serializer.read_exact(&mut header);
+        assert_eq!(end, Err(async_serialize::Incomplete { read: 0 }));
+        (header, csv)
+    });
+    assert_eq!(&header, b"0,4,");
+    assert_eq!(csv, b"1,2,3,4\n1,8,1,2,3,4,5,6,7,8\n");
 }
@@ -0,0 +1,189 @@
+use std::{cell::RefCell, pin::pin, time::Duration};
+
+use async_state_machine_example::{
+    busy_wait, retry, timeout, AsyncStateMachine, Clock, Mailbox, MockClock, TimedOut,
+};
+
+const SECTOR_SIZE: usize = 4096;
+const PAGE_SIZE: usize = 256;
+
+/// A fake SPI NOR flash, speaking a subset of the common command set
+///
+/// Erase and program operations set the write-in-progress bit in the status register until they
+/// complete. To exercise retries, the first program operation silently fails to write.
+struct MockFlash {
+    mem: Vec<u8>,
+    write_enabled: bool,
+    busy_until: Duration,
+    flaky_program: bool,
+}
+
+impl MockFlash {
+    const WREN: u8 = 0x06;
+    const RDSR: u8 = 0x05;
+    const READ: u8 = 0x03;
+    const PAGE_PROGRAM: u8 = 0x02;
+    const SECTOR_ERASE: u8 = 0x20;
+
+    const STATUS_WIP: u8 = 0x01;
+
+    const ERASE_TIME: Duration = Duration::from_millis(45);
+    const PROGRAM_TIME: Duration = Duration::from_millis(2);
+
+    fn new(size: usize) -> Self {
+        Self {
+            mem: vec![0xff; size],
+            write_enabled: false,
+            busy_until: Duration::ZERO,
+            flaky_program: true,
+        }
+    }
+
+    /// Perform one SPI transaction: `tx` is clocked out, and the response is clocked into `rx`
+    fn transfer(&mut self, tx: &[u8], rx: &mut [u8], now: Duration) {
+        let busy = now < self.busy_until;
+        let addr = || u32::from_be_bytes([0, tx[1], tx[2], tx[3]]) as usize;
+        match tx[0] {
+            Self::RDSR => rx[0] = if busy { Self::STATUS_WIP } else { 0 },
+            // The flash ignores everything but status reads while busy
+            _ if busy => (),
+            Self::WREN => self.write_enabled = true,
+            Self::READ => rx.copy_from_slice(&self.mem[addr()..addr() + rx.len()]),
+            Self::SECTOR_ERASE if self.write_enabled => {
+                let start = addr() & !(SECTOR_SIZE - 1);
+                self.mem[start..start + SECTOR_SIZE].fill(0xff);
+                self.write_enabled = false;
+                self.busy_until = now + Self::ERASE_TIME;
+            }
+            Self::PAGE_PROGRAM if self.write_enabled => {
+                if !self.flaky_program {
+                    let data = &tx[4..];
+                    // Programming can only clear bits
+                    for (cell, byte) in self.mem[addr()..addr() + data.len()].iter_mut().zip(data) {
+                        *cell &= byte;
+                    }
+                }
+                self.flaky_program = false;
+                self.write_enabled = false;
+                self.busy_until = now + Self::PROGRAM_TIME;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum FlashError {
+    Timeout,
+    VerifyFailed,
+}
+
+impl From<TimedOut> for FlashError {
+    fn from(_: TimedOut) -> Self {
+        FlashError::Timeout
+    }
+}
+
+/// An async driver for the flash, borrowing the SPI bus and the clock
+struct Flash<'a, C: Clock> {
+    spi: &'a RefCell<MockFlash>,
+    clock: &'a C,
+}
+
+impl<C: Clock> Flash<'_, C> {
+    const ERASE_TIMEOUT: Duration = Duration::from_millis(400);
+    const PROGRAM_TIMEOUT: Duration = Duration::from_millis(5);
+
+    fn transfer(&self, tx: &[u8], rx: &mut [u8]) {
+        self.spi.borrow_mut().transfer(tx, rx, self.clock.now());
+    }
+
+    fn is_busy(&self) -> bool {
+        let mut status = [0];
+        self.transfer(&[MockFlash::RDSR], &mut status);
+        status[0] & MockFlash::STATUS_WIP != 0
+    }
+
+    fn command(&self, cmd: u8, addr: usize, data: &[u8]) {
+        let mut tx = vec![cmd];
+        tx.extend_from_slice(&(addr as u32).to_be_bytes()[1..]);
+        tx.extend_from_slice(data);
+        self.transfer(&[MockFlash::WREN], &mut []);
+        self.transfer(&tx, &mut []);
+    }
+
+    fn read(&self, addr: usize, buf: &mut [u8]) {
+        let tx = [MockFlash::READ, (addr >> 16) as u8, (addr >> 8) as u8, addr as u8];
+        self.transfer(&tx, buf);
+    }
+
+    async fn erase_sector(&self, addr: usize) -> Result<(), FlashError> {
+        // A previous operation may still be in progress
+        busy_wait(|| self.is_busy(), Self::ERASE_TIMEOUT, self.clock).await?;
+        self.command(MockFlash::SECTOR_ERASE, addr, &[]);
+        busy_wait(|| self.is_busy(), Self::ERASE_TIMEOUT, self.clock).await?;
+        Ok(())
+    }
+
+    /// Program a page, and read it back to check that the write took
+    async fn program_verified(&self, addr: usize, data: &[u8]) -> Result<(), FlashError> {
+        busy_wait(|| self.is_busy(), Self::PROGRAM_TIMEOUT, self.clock).await?;
+        self.command(MockFlash::PAGE_PROGRAM, addr, data);
+        busy_wait(|| self.is_busy(), Self::PROGRAM_TIMEOUT, self.clock).await?;
+
+        let mut readback = [0; PAGE_SIZE];
+        let readback = &mut readback[..data.len()];
+        self.read(addr, readback);
+        if readback == data { Ok(()) } else { Err(FlashError::VerifyFailed) }
+    }
+
+    /// Erase a sector, and write `data` to the start of it a page at a time
+    async fn write_sector(&self, addr: usize, data: &[u8]) -> Result<(), FlashError> {
+        self.erase_sector(addr).await?;
+        for (i, page) in data.chunks(PAGE_SIZE).enumerate() {
+            let page_addr = addr + i * PAGE_SIZE;
+            retry(3, || self.program_verified(page_addr, page)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Wait for the application to request a write, then perform it
+async fn flash_task<C: Clock>(
+    flash: Flash<'_, C>,
+    request: &Mailbox<Vec<u8>>,
+) -> Result<Vec<u8>, FlashError> {
+    // Give up if nothing is requested within a second
+    let data = timeout(Duration::from_secs(1), flash.clock, request.recv()).await?;
+
+    flash.write_sector(SECTOR_SIZE, &data).await?;
+
+    let mut readback = vec![0; data.len()];
+    flash.read(SECTOR_SIZE, &mut readback);
+    Ok(readback)
+}
+
+fn main() {
+    let clock = MockClock::new();
+    let spi = RefCell::new(MockFlash::new(4 * SECTOR_SIZE));
+    let request = Mailbox::new();
+    let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+
+    let fut = pin!(flash_task(Flash { spi: &spi, clock: &clock }, &request));
+    let mut fsm = AsyncStateMachine::new(fut);
+
+    let mut ticks = 0;
+    let result = loop {
+        if let Some(result) = fsm.exec() {
+            break result;
+        }
+        if ticks == 10 {
+            request.post(data.clone()).unwrap();
+        }
+        clock.advance(Duration::from_millis(1));
+        ticks += 1;
+    };
+
+    assert_eq!(result, Ok(data));
+    println!("Write completed and verified after {:?}", clock.now());
+}
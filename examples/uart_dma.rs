@@ -0,0 +1,142 @@
+//! A serializer feeding a (mock) UART through a DMA double buffer
+//!
+//! The serializer machine fills one half of the buffer while the DMA engine transmits the other.
+//! Free halves are tracked with [`Credits`]: the DMA grants one credit each time it finishes
+//! transmitting a half, and the serializer has to acquire a credit before it can start filling
+//! one. When the UART can't keep up, the serializer simply yields until space is available.
+
+use std::{cell::RefCell, pin::pin};
+
+use async_state_machine_example::{AsyncStateMachine, AtomicOption, Credits};
+
+const HALF_SIZE: usize = 8;
+
+/// The buffer shared between the serializer and the DMA engine
+struct DoubleBuffer {
+    halves: [RefCell<[u8; HALF_SIZE]>; 2],
+    /// The number of bytes in each half which are ready to transmit, set by the serializer
+    ready: [AtomicOption<usize>; 2],
+    /// The number of halves free to be filled, granted by the DMA engine
+    free: Credits,
+}
+
+impl DoubleBuffer {
+    fn new() -> Self {
+        Self {
+            halves: Default::default(),
+            ready: Default::default(),
+            free: Credits::new(2),
+        }
+    }
+}
+
+/// Buffers bytes from the serializer into the double buffer
+struct BufferWriter<'a> {
+    buf: &'a DoubleBuffer,
+    half: usize,
+    pos: usize,
+}
+
+impl<'a> BufferWriter<'a> {
+    fn new(buf: &'a DoubleBuffer) -> Self {
+        Self { buf, half: 0, pos: 0 }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.pos == 0 {
+                // Wait for the DMA to hand back the half we are about to fill
+                self.buf.free.acquire(1).await;
+            }
+            self.buf.halves[self.half].borrow_mut()[self.pos] = b;
+            self.pos += 1;
+            if self.pos == HALF_SIZE {
+                self.submit();
+            }
+        }
+    }
+
+    /// Hand the current half to the DMA, even if it is only partially full
+    fn submit(&mut self) {
+        if self.pos > 0 {
+            self.buf.ready[self.half].store(Some(self.pos));
+            self.half ^= 1;
+            self.pos = 0;
+        }
+    }
+}
+
+/// Serializes a list of records, each one framed with a length and a type byte
+async fn serializer(records: &[(u8, &[u8])], mut out: BufferWriter<'_>) {
+    for (record_type, data) in records {
+        let len = (data.len() + 1) as u16;
+        out.write(&len.to_le_bytes()).await;
+        out.write(&[*record_type]).await;
+        out.write(data).await;
+    }
+    out.submit();
+}
+
+/// A fake UART DMA channel, which transmits a fixed number of bytes per tick
+struct MockDma {
+    half: usize,
+    pos: usize,
+    wire: Vec<u8>,
+}
+
+impl MockDma {
+    const BYTES_PER_TICK: usize = 3;
+
+    fn new() -> Self {
+        Self { half: 0, pos: 0, wire: Vec::new() }
+    }
+
+    fn step(&mut self, buf: &DoubleBuffer) {
+        for _ in 0..Self::BYTES_PER_TICK {
+            let Some(len) = buf.ready[self.half].load() else {
+                return;
+            };
+            self.wire.push(buf.halves[self.half].borrow()[self.pos]);
+            self.pos += 1;
+            if self.pos == len {
+                // Half complete; this would be the transfer-complete interrupt
+                buf.ready[self.half].store(None);
+                buf.free.grant(1);
+                self.half ^= 1;
+                self.pos = 0;
+            }
+        }
+    }
+}
+
+fn main() {
+    let records: [(u8, &[u8]); 3] = [
+        (0, &[1, 2, 3, 4]),
+        (1, &[10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21]),
+        (2, &[]),
+    ];
+
+    let buf = DoubleBuffer::new();
+    let mut dma = MockDma::new();
+    let fut = pin!(serializer(&records, BufferWriter::new(&buf)));
+    let mut fsm = AsyncStateMachine::new(fut);
+
+    let mut polls = 0;
+    let mut done = false;
+    while !done || buf.free.available() < 2 {
+        if !done {
+            done = fsm.exec().is_some();
+            polls += 1;
+        }
+        dma.step(&buf);
+    }
+
+    let mut expected = Vec::new();
+    for (record_type, data) in records {
+        expected.extend_from_slice(&((data.len() + 1) as u16).to_le_bytes());
+        expected.push(record_type);
+        expected.extend_from_slice(data);
+    }
+    assert_eq!(dma.wire, expected);
+    println!("Transmitted {} bytes using {} serializer polls", dma.wire.len(), polls);
+}
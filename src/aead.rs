@@ -0,0 +1,267 @@
+//! Authenticated encryption stages, for records which must be kept secret at rest
+
+use crate::{ByteSink, Stage};
+
+/// The number of plaintext bytes in each encrypted segment
+const SEGMENT: usize = 64;
+const TAG: usize = 16;
+
+/// An AEAD cipher with a 96-bit nonce and a 128-bit tag, such as ChaCha20-Poly1305
+///
+/// With the `chacha20poly1305` feature, this is implemented for the RustCrypto
+/// `ChaCha20Poly1305` type.
+pub trait Cipher {
+    /// Encrypt `buf` in place, returning its tag
+    fn encrypt(&self, nonce: &[u8; 12], buf: &mut [u8]) -> [u8; TAG];
+
+    /// Decrypt `buf` in place, if `tag` is valid for it
+    fn decrypt(&self, nonce: &[u8; 12], buf: &mut [u8], tag: &[u8; TAG]) -> Result<(), AuthFailed>;
+}
+
+/// Error from [`Decrypt`] for input which was tampered with, truncated, or encrypted with a
+/// different key or nonce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthFailed;
+
+impl core::fmt::Display for AuthFailed {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("authentication failed")
+    }
+}
+
+impl core::error::Error for AuthFailed {}
+
+/// The nonce for one segment: the stream's prefix, the segment counter, and a flag marking the
+/// final segment, so that segments can't be reordered, dropped or truncated undetected
+fn segment_nonce(prefix: &[u8; 7], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..7].copy_from_slice(prefix);
+    nonce[7..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// A [`Stage`] which encrypts and authenticates the stream
+///
+/// The stream is split into 64 byte segments, each of which is written encrypted and followed by
+/// its tag, so memory use stays fixed however long the stream is. Each stream must use a
+/// different `nonce_prefix` with the same key.
+///
+/// Segments are numbered with a 32-bit counter, so a stream can be at most `2^32` segments
+/// (256 GiB) long. Writing more panics rather than reusing a nonce.
+pub struct Encrypt<C: Cipher> {
+    cipher: C,
+    nonce_prefix: [u8; 7],
+    /// The next segment's number, or `None` once every number has been used
+    counter: Option<u32>,
+    buf: [u8; SEGMENT],
+    len: usize,
+}
+
+impl<C: Cipher> Encrypt<C> {
+    /// Encrypt with `cipher`, using `nonce_prefix` to keep this stream's nonces unique
+    pub fn new(cipher: C, nonce_prefix: [u8; 7]) -> Self {
+        Self { cipher, nonce_prefix, counter: Some(0), buf: [0; SEGMENT], len: 0 }
+    }
+
+    async fn flush_segment(&mut self, last: bool, out: &mut impl ByteSink) {
+        let counter = self.counter.expect("stream too long to encrypt without reusing a nonce");
+        let nonce = segment_nonce(&self.nonce_prefix, counter, last);
+        let tag = self.cipher.encrypt(&nonce, &mut self.buf[..self.len]);
+        self.counter = counter.checked_add(1);
+        out.write(&self.buf[..self.len]).await;
+        out.write(&tag).await;
+        self.len = 0;
+    }
+}
+
+impl<C: Cipher> Stage for Encrypt<C> {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &b in bytes {
+            // A full segment is only written once more data arrives, since until then it may be
+            // the last one
+            if self.len == SEGMENT {
+                self.flush_segment(false, out).await;
+            }
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        self.flush_segment(true, out).await;
+    }
+}
+
+/// A [`Stage`] which reverses [`Encrypt`]
+///
+/// Each segment is only passed on once its tag has been verified. A failure can't be reported
+/// through the stream, so it is recorded and all further input is discarded; check
+/// [`Decrypt::error`] once the stream is finished. Segments before a truncation have already
+/// been passed on by then, so the output must not be acted on until the error has been checked.
+pub struct Decrypt<C: Cipher> {
+    cipher: C,
+    nonce_prefix: [u8; 7],
+    /// The next segment's number, or `None` once every number has been used
+    counter: Option<u32>,
+    buf: [u8; SEGMENT + TAG],
+    len: usize,
+    error: Option<AuthFailed>,
+}
+
+impl<C: Cipher> Decrypt<C> {
+    /// Decrypt a stream encrypted with the same key and `nonce_prefix`
+    pub fn new(cipher: C, nonce_prefix: [u8; 7]) -> Self {
+        let buf = [0; SEGMENT + TAG];
+        Self { cipher, nonce_prefix, counter: Some(0), buf, len: 0, error: None }
+    }
+
+    /// The error which stopped decryption, if any
+    pub fn error(&self) -> Option<AuthFailed> {
+        self.error
+    }
+
+    async fn flush_segment(&mut self, last: bool, out: &mut impl ByteSink) {
+        // A stream longer than `Encrypt` will produce can't be authentic
+        let (Some(data_len), Some(counter)) = (self.len.checked_sub(TAG), self.counter) else {
+            self.error = Some(AuthFailed);
+            return;
+        };
+        let nonce = segment_nonce(&self.nonce_prefix, counter, last);
+        let (data, tag) = self.buf[..self.len].split_at_mut(data_len);
+        match self.cipher.decrypt(&nonce, data, (&*tag).try_into().unwrap()) {
+            Ok(()) => out.write(data).await,
+            Err(e) => self.error = Some(e),
+        }
+        self.counter = counter.checked_add(1);
+        self.len = 0;
+    }
+}
+
+impl<C: Cipher> Stage for Decrypt<C> {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &b in bytes {
+            if self.error.is_some() {
+                return;
+            }
+            if self.len == self.buf.len() {
+                self.flush_segment(false, out).await;
+            }
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        if self.error.is_none() {
+            self.flush_segment(true, out).await;
+        }
+    }
+}
+
+#[cfg(feature = "chacha20poly1305")]
+impl Cipher for chacha20poly1305::ChaCha20Poly1305 {
+    fn encrypt(&self, nonce: &[u8; 12], buf: &mut [u8]) -> [u8; TAG] {
+        use chacha20poly1305::AeadInPlace;
+
+        // Encryption only fails for messages far longer than a segment
+        let tag = self.encrypt_in_place_detached(nonce.into(), &[], buf).unwrap();
+        tag.into()
+    }
+
+    fn decrypt(&self, nonce: &[u8; 12], buf: &mut [u8], tag: &[u8; TAG]) -> Result<(), AuthFailed> {
+        use chacha20poly1305::AeadInPlace;
+
+        self.decrypt_in_place_detached(nonce.into(), &[], buf, tag.into()).map_err(|_| AuthFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in cipher, which is not remotely secure but authenticates well enough to test the
+    /// stages
+    struct XorCipher(u8);
+
+    impl XorCipher {
+        fn tag(&self, nonce: &[u8; 12], buf: &[u8]) -> [u8; TAG] {
+            let mut tag = [self.0; TAG];
+            for (i, &b) in nonce.iter().chain(buf).enumerate() {
+                tag[i % TAG] = tag[i % TAG].rotate_left(3) ^ b;
+            }
+            tag
+        }
+    }
+
+    impl Cipher for XorCipher {
+        fn encrypt(&self, nonce: &[u8; 12], buf: &mut [u8]) -> [u8; TAG] {
+            buf.iter_mut().for_each(|b| *b ^= self.0);
+            self.tag(nonce, buf)
+        }
+
+        fn decrypt(
+            &self,
+            nonce: &[u8; 12],
+            buf: &mut [u8],
+            tag: &[u8; TAG],
+        ) -> Result<(), AuthFailed> {
+            if self.tag(nonce, buf) != *tag {
+                return Err(AuthFailed);
+            }
+            buf.iter_mut().for_each(|b| *b ^= self.0);
+            Ok(())
+        }
+    }
+
+    /// Pass `input` through `stage` in chunks which don't line up with the segments
+    fn run<St: Stage>(stage: St, input: &[u8]) -> (St, Vec<u8>) {
+        crate::stage::run(stage, input, 5)
+    }
+
+    #[test]
+    fn test_round_trip_and_tampering() {
+        let prefix = *b"record1";
+        let plaintext: Vec<u8> = (0..150).collect();
+        let (_, ciphertext) = run(Encrypt::new(XorCipher(0x5a), prefix), &plaintext);
+        // Three segments, each with a tag
+        assert_eq!(ciphertext.len(), plaintext.len() + 3 * TAG);
+
+        let (decrypt, output) = run(Decrypt::new(XorCipher(0x5a), prefix), &ciphertext);
+        assert_eq!(decrypt.error(), None);
+        assert_eq!(output, plaintext);
+
+        let mut tampered = ciphertext.clone();
+        tampered[100] ^= 1;
+        let (decrypt, output) = run(Decrypt::new(XorCipher(0x5a), prefix), &tampered);
+        assert_eq!(decrypt.error(), Some(AuthFailed));
+        assert_eq!(output, plaintext[..SEGMENT]);
+
+        // Dropping the final segment leaves a non-final segment at the end
+        let truncated = &ciphertext[..2 * (SEGMENT + TAG)];
+        let (decrypt, _) = run(Decrypt::new(XorCipher(0x5a), prefix), truncated);
+        assert_eq!(decrypt.error(), Some(AuthFailed));
+    }
+
+    #[test]
+    #[should_panic(expected = "stream too long to encrypt without reusing a nonce")]
+    fn test_encrypt_refuses_to_reuse_nonce() {
+        let mut encrypt = Encrypt::new(XorCipher(0x5a), *b"record1");
+        encrypt.counter = Some(u32::MAX);
+        run(encrypt, &[0; SEGMENT + 1]);
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_chacha20poly1305() {
+        use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
+
+        let key = [7; 32];
+        let prefix = *b"record2";
+        let cipher = || ChaCha20Poly1305::new(&key.into());
+        let (_, ciphertext) = run(Encrypt::new(cipher(), prefix), b"pin=1234");
+        let (decrypt, output) = run(Decrypt::new(cipher(), prefix), &ciphertext);
+        assert_eq!(decrypt.error(), None);
+        assert_eq!(output, b"pin=1234");
+    }
+}
@@ -0,0 +1,236 @@
+//! Text armor stages, for binary data sent over text-only channels
+
+use crate::{ByteSink, Stage};
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Error from a decoding stage for input which is not validly encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidEncoding;
+
+impl core::fmt::Display for InvalidEncoding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("invalid encoding")
+    }
+}
+
+impl core::error::Error for InvalidEncoding {}
+
+/// A [`Stage`] which writes each byte as two lowercase hex digits
+#[derive(Default)]
+pub struct HexEncode;
+
+impl Stage for HexEncode {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &b in bytes {
+            out.write(&[HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]).await;
+        }
+    }
+}
+
+/// A [`Stage`] which reverses [`HexEncode`], accepting either case
+///
+/// As for [`Decompress`](crate::Decompress), the first error is recorded and further input is
+/// discarded; check [`HexDecode::error`] once the stream is finished.
+#[derive(Default)]
+pub struct HexDecode {
+    high: Option<u8>,
+    error: Option<InvalidEncoding>,
+}
+
+impl HexDecode {
+    /// Create a decoder, which accepts upper and lower case digits
+    pub const fn new() -> Self {
+        Self { high: None, error: None }
+    }
+
+    /// The error which stopped decoding, if any
+    pub fn error(&self) -> Option<InvalidEncoding> {
+        self.error
+    }
+}
+
+impl Stage for HexDecode {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &c in bytes {
+            if self.error.is_some() {
+                return;
+            }
+            let Some(digit) = (c as char).to_digit(16) else {
+                self.error = Some(InvalidEncoding);
+                return;
+            };
+            match self.high.take() {
+                Some(high) => out.write(&[high << 4 | digit as u8]).await,
+                None => self.high = Some(digit as u8),
+            }
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        let _ = out;
+        if self.high.is_some() {
+            self.error.get_or_insert(InvalidEncoding);
+        }
+    }
+}
+
+/// A [`Stage`] which encodes the stream as standard, padded Base64
+#[derive(Default)]
+pub struct Base64Encode {
+    group: [u8; 3],
+    len: usize,
+}
+
+impl Base64Encode {
+    /// Create an encoder for the standard alphabet, with padding
+    pub const fn new() -> Self {
+        Self { group: [0; 3], len: 0 }
+    }
+
+    async fn flush_group(&mut self, out: &mut impl ByteSink) {
+        let [a, b, c] = self.group;
+        let mut chars = [
+            BASE64[(a >> 2) as usize],
+            BASE64[((a & 0x3) << 4 | b >> 4) as usize],
+            BASE64[((b & 0xf) << 2 | c >> 6) as usize],
+            BASE64[(c & 0x3f) as usize],
+        ];
+        chars[self.len + 1..].fill(b'=');
+        out.write(&chars).await;
+        self.group = [0; 3];
+        self.len = 0;
+    }
+}
+
+impl Stage for Base64Encode {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &b in bytes {
+            self.group[self.len] = b;
+            self.len += 1;
+            if self.len == 3 {
+                self.flush_group(out).await;
+            }
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        if self.len > 0 {
+            self.flush_group(out).await;
+        }
+    }
+}
+
+/// A [`Stage`] which reverses [`Base64Encode`]
+///
+/// Padding is required. As for [`HexDecode`], the first error is recorded and further input is
+/// discarded; check [`Base64Decode::error`] once the stream is finished.
+#[derive(Default)]
+pub struct Base64Decode {
+    group: [u8; 4],
+    len: usize,
+    /// The number of padding characters in the current group
+    padding: usize,
+    /// Whether a padded group has ended the stream
+    ended: bool,
+    error: Option<InvalidEncoding>,
+}
+
+impl Base64Decode {
+    /// Create a decoder for the standard alphabet, which requires padding
+    pub const fn new() -> Self {
+        Self { group: [0; 4], len: 0, padding: 0, ended: false, error: None }
+    }
+
+    /// The error which stopped decoding, if any
+    pub fn error(&self) -> Option<InvalidEncoding> {
+        self.error
+    }
+
+    fn decode_char(&mut self, c: u8) -> Option<u8> {
+        if self.ended {
+            return None;
+        }
+        if c == b'=' {
+            // At most two padding characters, at the end of a group
+            if self.len < 2 {
+                return None;
+            }
+            self.padding += 1;
+            return Some(0);
+        }
+        if self.padding > 0 {
+            return None;
+        }
+        BASE64.iter().position(|&x| x == c).map(|v| v as u8)
+    }
+}
+
+impl Stage for Base64Decode {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &c in bytes {
+            if self.error.is_some() {
+                return;
+            }
+            let Some(value) = self.decode_char(c) else {
+                self.error = Some(InvalidEncoding);
+                return;
+            };
+            self.group[self.len] = value;
+            self.len += 1;
+            if self.len == 4 {
+                let [a, b, c, d] = self.group;
+                let bytes = [a << 2 | b >> 4, b << 4 | c >> 2, c << 6 | d];
+                out.write(&bytes[..3 - self.padding]).await;
+                self.ended = self.padding > 0;
+                self.len = 0;
+                self.padding = 0;
+            }
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        let _ = out;
+        if self.len != 0 {
+            self.error.get_or_insert(InvalidEncoding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pass `input` through `stage` one byte at a time
+    fn run<St: Stage>(stage: St, input: &[u8]) -> (St, Vec<u8>) {
+        crate::stage::run(stage, input, 1)
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(run(HexEncode, &[0x01, 0xab, 0xff]).1, b"01abff");
+        let (decode, output) = run(HexDecode::new(), b"01ABff");
+        assert_eq!((decode.error(), output), (None, vec![0x01, 0xab, 0xff]));
+        assert_eq!(run(HexDecode::new(), b"0g").0.error(), Some(InvalidEncoding));
+        assert_eq!(run(HexDecode::new(), b"012").0.error(), Some(InvalidEncoding));
+    }
+
+    #[test]
+    fn test_base64() {
+        for (plain, encoded) in [
+            (&b""[..], &b""[..]),
+            (b"f", b"Zg=="),
+            (b"fo", b"Zm8="),
+            (b"foo", b"Zm9v"),
+            (b"foobar", b"Zm9vYmFy"),
+        ] {
+            assert_eq!(run(Base64Encode::new(), plain).1, encoded);
+            let (decode, output) = run(Base64Decode::new(), encoded);
+            assert_eq!((decode.error(), output.as_slice()), (None, plain));
+        }
+        for invalid in [&b"Zm9"[..], b"Zg==Zg==", b"Z===", b"Zm=v", b"Zm9*"] {
+            assert_eq!(run(Base64Decode::new(), invalid).0.error(), Some(InvalidEncoding));
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! An executor for a fixed array of identical machines
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use crate::{CancelToken, Clock, IdleStrategy, Mailbox};
+
+/// The state of one slot of an [`ArrayExecutor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotStatus {
+    /// The machine has not completed yet
+    Running,
+    /// The machine has completed, and its output has not been taken
+    Finished,
+    /// The machine has completed, and its output has been taken
+    Empty,
+    /// The machine was dropped by [`ArrayExecutor::abort`] before it completed
+    Aborted,
+}
+
+/// A snapshot of one slot of an [`ArrayExecutor`], from [`ArrayExecutor::iter_machines`]
+///
+/// The await point a machine is suspended at is not visible from outside the future; wrap the
+/// machine with [`instrument_machine`](crate::instrument_machine) to count polls per await point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineInfo {
+    pub index: usize,
+    /// The name given with [`ArrayExecutor::set_name`], if any
+    pub name: Option<&'static str>,
+    pub status: SlotStatus,
+    /// The number of times the machine has been polled
+    pub polls: usize,
+    /// The total time spent polling the machine, as measured by [`ArrayExecutor::tick_within`]
+    pub busy: Duration,
+}
+
+/// A handle to one machine of an [`ArrayExecutor`], from [`ArrayExecutor::handles`]
+///
+/// The handle is a plain copyable token, so it can be handed to whatever decides when a machine
+/// should be stopped without borrowing the executor; the executor is passed back in to act on
+/// it. Each handle records which executor it came from, and panics if passed to another one.
+/// Slots are filled once, when the executor is created, and never reused, so a handle always
+/// refers to the machine it was taken for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbortHandle {
+    executor: usize,
+    index: usize,
+}
+
+impl AbortHandle {
+    /// The slot of the machine this handle refers to
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Ask the machine to stop, through the [`CancelToken`] it was created with
+    ///
+    /// Returns false if the machine has already completed, or the executor was created without
+    /// tokens by [`ArrayExecutor::new`].
+    pub fn cancel<F: Future, C, const N: usize>(&self, exec: &ArrayExecutor<'_, F, C, N>) -> bool {
+        self.check(exec.id);
+        match exec.cancels {
+            Some(cancels) if !self.is_finished(exec) => {
+                cancels[self.index].cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drop the machine without letting it complete
+    ///
+    /// See [`ArrayExecutor::abort`].
+    pub fn abort<F: Future, C, const N: usize>(
+        &self,
+        exec: Pin<&mut ArrayExecutor<'_, F, C, N>>,
+    ) -> bool {
+        self.check(exec.id);
+        exec.abort(self.index)
+    }
+
+    /// True once the machine has completed or been aborted
+    pub fn is_finished<F: Future, C, const N: usize>(
+        &self,
+        exec: &ArrayExecutor<'_, F, C, N>,
+    ) -> bool {
+        self.check(exec.id);
+        exec.status(self.index) != SlotStatus::Running
+    }
+
+    fn check(&self, executor: usize) {
+        assert_eq!(self.executor, executor, "handle used with a different executor");
+    }
+}
+
+/// Allocate an ID to tell executors apart, for checking [`AbortHandle`]s
+fn next_executor_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    #[cfg(target_has_atomic = "ptr")]
+    {
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+    #[cfg(not(target_has_atomic = "ptr"))]
+    critical_section::with(|_| {
+        let id = NEXT_ID.load(Ordering::Relaxed);
+        NEXT_ID.store(id.wrapping_add(1), Ordering::Relaxed);
+        id
+    })
+}
+
+/// A budget overrun reported by [`ArrayExecutor::tick_within`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetOverrun {
+    /// Polling the machine in slot `index` took longer than its share of the budget
+    Machine { index: usize, elapsed: Duration },
+    /// The whole tick took longer than the budget
+    Tick { elapsed: Duration },
+}
+
+enum Slot<F: Future> {
+    Running(F),
+    Finished(Option<F::Output>),
+    Aborted,
+}
+
+/// Runs `N` machines of the same type, created from one factory
+///
+/// Each machine is given its index and its own [`Mailbox`], through which commands can be
+/// injected with [`ArrayExecutor::send`]. The machines are stored inline, with no boxing or type
+/// erasure, so the executor has to be pinned (e.g. with `pin!`) before it can be ticked.
+///
+/// There is no way to tick until the system has settled, because a pass which made no progress
+/// can't be told apart from one which did. The waiting primitives in this crate, such as
+/// [`Mailbox::recv`], poll their condition and wake on every yield, so a machine waiting on one
+/// never reports [`Step::Stalled`](crate::Step::Stalled). Tests can instead tick a fixed number
+/// of times, or until [`ArrayExecutor::status`] shows the machines they are waiting for have
+/// finished.
+///
+/// ```ignore
+/// let inboxes: [Mailbox<Command>; 16] = Default::default();
+/// let mut channels = pin!(ArrayExecutor::new(&inboxes, |i, inbox| channel_handler(i, inbox)));
+/// channels.send(3, Command::Enable).ok();
+/// loop {
+///     channels.as_mut().tick();
+/// }
+/// ```
+pub struct ArrayExecutor<'a, F: Future, C, const N: usize> {
+    slots: [Slot<F>; N],
+    polls: [usize; N],
+    busy: [Duration; N],
+    names: [Option<&'static str>; N],
+    inboxes: &'a [Mailbox<C>; N],
+    cancels: Option<&'a [CancelToken; N]>,
+    id: usize,
+}
+
+impl<'a, F: Future, C, const N: usize> ArrayExecutor<'a, F, C, N> {
+    /// Create the machines, calling `factory` with each slot's index and mailbox
+    pub fn new(
+        inboxes: &'a [Mailbox<C>; N],
+        mut factory: impl FnMut(usize, &'a Mailbox<C>) -> F,
+    ) -> Self {
+        let slots = core::array::from_fn(|i| Slot::Running(factory(i, &inboxes[i])));
+        Self::from_slots(slots, inboxes, None)
+    }
+
+    /// Create the machines, calling `factory` with each slot's index, mailbox and the token
+    /// through which [`AbortHandle::cancel`] asks it to stop
+    pub fn with_cancel(
+        inboxes: &'a [Mailbox<C>; N],
+        cancels: &'a [CancelToken; N],
+        mut factory: impl FnMut(usize, &'a Mailbox<C>, &'a CancelToken) -> F,
+    ) -> Self {
+        let slots = core::array::from_fn(|i| Slot::Running(factory(i, &inboxes[i], &cancels[i])));
+        Self::from_slots(slots, inboxes, Some(cancels))
+    }
+
+    fn from_slots(
+        slots: [Slot<F>; N],
+        inboxes: &'a [Mailbox<C>; N],
+        cancels: Option<&'a [CancelToken; N]>,
+    ) -> Self {
+        Self {
+            slots,
+            polls: [0; N],
+            busy: [Duration::ZERO; N],
+            names: [None; N],
+            inboxes,
+            cancels,
+            id: next_executor_id(),
+        }
+    }
+
+    /// Poll every running machine once
+    ///
+    /// Returns the number of machines still running afterwards.
+    pub fn tick(self: Pin<&mut Self>) -> usize {
+        self.poll_all(Waker::noop(), || Duration::ZERO, |_, _| ())
+    }
+
+    /// Tick until every machine has completed, idling with `idle` between ticks
+    ///
+    /// Machines are polled with the strategy's waker, so machines which wake it, or interrupt
+    /// handlers which do, end the idle early. Machines waiting on something which never wakes,
+    /// such as [`Input::take_input`](crate::Input::take_input), are only polled again when the
+    /// strategy returns by itself, e.g. on a timer interrupt, or straight away with
+    /// [`Spin`](crate::Spin).
+    pub fn run(mut self: Pin<&mut Self>, idle: &mut impl IdleStrategy) {
+        let waker = idle.waker();
+        while self.as_mut().poll_all(&waker, || Duration::ZERO, |_, _| ()) > 0 {
+            idle.idle(Duration::MAX);
+        }
+    }
+
+    /// Poll every running machine once, timing each poll against a budget for the whole tick
+    ///
+    /// Each running machine's share of the budget is an equal split between them. `on_overrun`
+    /// is called for every machine which takes longer than its share, and once more at the end
+    /// if the whole tick took longer than `budget`. Machines are never skipped to make up time,
+    /// since a machine part way through a poll can't be interrupted; the callback is for raising
+    /// the alarm. Returns the number of machines still running afterwards.
+    pub fn tick_within(
+        self: Pin<&mut Self>,
+        budget: Duration,
+        clock: &impl Clock,
+        mut on_overrun: impl FnMut(BudgetOverrun),
+    ) -> usize {
+        let running = self.slots.iter().filter(|slot| matches!(slot, Slot::Running(_))).count();
+        let share = budget / running.max(1) as u32;
+        let start = clock.now();
+        let still_running = self.poll_all(
+            Waker::noop(),
+            || clock.now(),
+            |index, elapsed| {
+                if elapsed > share {
+                    on_overrun(BudgetOverrun::Machine { index, elapsed });
+                }
+            },
+        );
+        let elapsed = clock.now() - start;
+        if elapsed > budget {
+            on_overrun(BudgetOverrun::Tick { elapsed });
+        }
+        still_running
+    }
+
+    /// Poll every running machine, reporting the time each poll took from `now` to `observe`
+    fn poll_all(
+        self: Pin<&mut Self>,
+        waker: &Waker,
+        now: impl Fn() -> Duration,
+        mut observe: impl FnMut(usize, Duration),
+    ) -> usize {
+        // SAFETY: The slots are never moved out of the pinned executor. A completed machine is
+        // dropped in place when its slot is overwritten.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut cx = Context::from_waker(waker);
+        let mut running = 0;
+        for (index, slot) in this.slots.iter_mut().enumerate() {
+            if let Slot::Running(fut) = slot {
+                this.polls[index] += 1;
+                // SAFETY: See above; `fut` is structurally pinned inside the executor
+                let fut = unsafe { Pin::new_unchecked(fut) };
+                let start = now();
+                let result = fut.poll(&mut cx);
+                let elapsed = now() - start;
+                this.busy[index] += elapsed;
+                observe(index, elapsed);
+                match result {
+                    Poll::Ready(output) => *slot = Slot::Finished(Some(output)),
+                    Poll::Pending => running += 1,
+                }
+            }
+        }
+        running
+    }
+
+    /// Post a command to the machine in slot `index`
+    ///
+    /// Returns the command back if that machine's mailbox is still full.
+    pub fn send(&self, index: usize, cmd: C) -> Result<(), C> {
+        self.inboxes[index].post(cmd)
+    }
+
+    pub fn status(&self, index: usize) -> SlotStatus {
+        match &self.slots[index] {
+            Slot::Running(_) => SlotStatus::Running,
+            Slot::Finished(Some(_)) => SlotStatus::Finished,
+            Slot::Finished(None) => SlotStatus::Empty,
+            Slot::Aborted => SlotStatus::Aborted,
+        }
+    }
+
+    /// Name the machine in slot `index`, for [`ArrayExecutor::iter_machines`]
+    pub fn set_name(self: Pin<&mut Self>, index: usize, name: &'static str) {
+        // SAFETY: Only the name is written; the machines are not touched
+        unsafe { self.get_unchecked_mut() }.names[index] = Some(name);
+    }
+
+    /// Describe every slot, e.g. for a debug console
+    pub fn iter_machines(&self) -> impl Iterator<Item = MachineInfo> + '_ {
+        (0..N).map(|index| MachineInfo {
+            index,
+            name: self.names[index],
+            status: self.status(index),
+            polls: self.polls[index],
+            busy: self.busy[index],
+        })
+    }
+
+    /// Take the output of the machine in slot `index`, if it has finished
+    pub fn take_output(self: Pin<&mut Self>, index: usize) -> Option<F::Output> {
+        // SAFETY: Only the output of a completed slot is moved out, never a machine
+        let slot = unsafe { &mut self.get_unchecked_mut().slots[index] };
+        match slot {
+            Slot::Finished(output) => output.take(),
+            Slot::Running(_) | Slot::Aborted => None,
+        }
+    }
+
+    /// A handle to each machine, in slot order
+    pub fn handles(&self) -> [AbortHandle; N] {
+        core::array::from_fn(|index| AbortHandle { executor: self.id, index })
+    }
+
+    /// Drop the machine in slot `index` without letting it complete
+    ///
+    /// The machine is dropped in place, so any guards it holds run their cleanup before this
+    /// returns. Returns false if the machine had already completed, in which case its output, if
+    /// any, is left to be taken.
+    pub fn abort(self: Pin<&mut Self>, index: usize) -> bool {
+        // SAFETY: A running machine is dropped in place and never moved
+        let slot = unsafe { &mut self.get_unchecked_mut().slots[index] };
+        match slot {
+            Slot::Running(_) => {
+                *slot = Slot::Aborted;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::pin::pin;
+
+    async fn channel(index: usize, inbox: &Mailbox<u32>) -> u32 {
+        let a = inbox.recv().await;
+        let b = inbox.recv().await;
+        a + b + index as u32
+    }
+
+    #[test]
+    fn test_array_executor() {
+        let inboxes: [Mailbox<u32>; 3] = Default::default();
+        let mut exec = pin!(ArrayExecutor::new(&inboxes, channel));
+
+        assert_eq!(exec.as_mut().tick(), 3);
+        exec.send(1, 10).unwrap();
+        assert_eq!(exec.send(1, 20), Err(20));
+        assert_eq!(exec.as_mut().tick(), 3);
+        exec.send(1, 20).unwrap();
+        assert_eq!(exec.as_mut().tick(), 2);
+        assert_eq!(exec.status(0), SlotStatus::Running);
+        assert_eq!(exec.status(1), SlotStatus::Finished);
+        assert_eq!(exec.as_mut().take_output(1), Some(31));
+        assert_eq!(exec.status(1), SlotStatus::Empty);
+        let polls: Vec<_> = exec.iter_machines().map(|info| info.polls).collect();
+        assert_eq!(polls, [3, 3, 3]);
+        exec.as_mut().set_name(0, "left");
+        let names: Vec<_> = exec.iter_machines().map(|info| info.name).collect();
+        assert_eq!(names, [Some("left"), None, None]);
+
+        let [_, first, second] = exec.handles();
+        assert!(!second.is_finished(&exec));
+        assert!(!second.cancel(&exec));
+        assert!(second.abort(exec.as_mut()));
+        assert!(!first.abort(exec.as_mut()));
+        assert!(second.is_finished(&exec));
+        assert_eq!(exec.status(2), SlotStatus::Aborted);
+        assert_eq!(exec.as_mut().tick(), 1);
+    }
+
+    #[test]
+    fn test_cancel_through_handle() {
+        let inboxes: [Mailbox<()>; 2] = Default::default();
+        let cancels: [CancelToken; 2] = Default::default();
+        let mut exec = pin!(ArrayExecutor::with_cancel(&inboxes, &cancels, |i, _, cancel| {
+            async move {
+                cancel.cancelled().await;
+                i
+            }
+        }));
+        let [first, second] = exec.handles();
+        assert_eq!(exec.as_mut().tick(), 2);
+        assert!(second.cancel(&exec));
+        assert_eq!(exec.as_mut().tick(), 1);
+        assert!(second.is_finished(&exec));
+        assert!(!second.cancel(&exec));
+        assert_eq!(exec.as_mut().take_output(1), Some(1));
+        assert!(!first.is_finished(&exec));
+    }
+
+    #[test]
+    #[should_panic(expected = "handle used with a different executor")]
+    fn test_handle_from_another_executor() {
+        let inboxes: [Mailbox<()>; 1] = Default::default();
+        let exec = pin!(ArrayExecutor::new(&inboxes, |_, _| async {}));
+        let other = pin!(ArrayExecutor::new(&inboxes, |_, _| async {}));
+        let [handle] = exec.handles();
+        handle.is_finished(&other);
+    }
+
+    #[test]
+    fn test_run_with_idle_strategy() {
+        let inboxes: [Mailbox<u32>; 2] = Default::default();
+        let mut exec = pin!(ArrayExecutor::new(&inboxes, channel));
+        let mut idles = 0;
+        exec.as_mut().run(&mut |_| {
+            idles += 1;
+            for inbox in &inboxes {
+                inbox.post(idles).unwrap();
+            }
+        });
+        assert_eq!(idles, 2);
+        assert_eq!(exec.as_mut().take_output(1), Some(4));
+    }
+
+    #[test]
+    fn test_tick_within_budget() {
+        use crate::MockClock;
+
+        const MS: Duration = Duration::from_millis(1);
+        let clock = MockClock::new();
+        let inboxes: [Mailbox<()>; 2] = Default::default();
+        // Each machine advances the clock by its index + 1 milliseconds per poll
+        let mut exec = pin!(ArrayExecutor::new(&inboxes, |i, _| {
+            let clock = &clock;
+            async move {
+                loop {
+                    clock.advance((i as u32 + 1) * MS);
+                    crate::yield_now().await;
+                }
+            }
+        }));
+
+        let mut overruns = Vec::new();
+        exec.as_mut().tick_within(4 * MS, &clock, |overrun| overruns.push(overrun));
+        assert!(overruns.is_empty());
+        exec.as_mut().tick_within(2 * MS, &clock, |overrun| overruns.push(overrun));
+        assert_eq!(overruns, [
+            BudgetOverrun::Machine { index: 1, elapsed: 2 * MS },
+            BudgetOverrun::Tick { elapsed: 3 * MS },
+        ]);
+        let busy: Vec<_> = exec.iter_machines().map(|info| info.busy).collect();
+        assert_eq!(busy, [2 * MS, 4 * MS]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Machines which suspend on requests answered by their caller
+
+use core::cell::Cell;
+use core::pin::Pin;
+
+use futures::pending;
+
+use crate::{AsyncStateMachine, Step};
+
+/// The channel through which a body asks its caller for something
+///
+/// `Q` is the request type and `A` the answer type, e.g. a register read request and the
+/// register's value.
+pub struct Asker<Q, A> {
+    request: Cell<Option<Q>>,
+    answer: Cell<Option<A>>,
+}
+
+impl<Q, A> Asker<Q, A> {
+    pub const fn new() -> Self {
+        Self { request: Cell::new(None), answer: Cell::new(None) }
+    }
+
+    /// Make a request of the caller, and wait for the answer
+    pub async fn ask(&self, request: Q) -> A {
+        self.answer.set(None);
+        self.request.set(Some(request));
+        loop {
+            if let Some(answer) = self.answer.take() {
+                return answer;
+            }
+            // Nothing can happen until the caller answers, so don't ask to be polled again
+            pending!()
+        }
+    }
+}
+
+impl<Q, A> Default for Asker<Q, A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A state machine which can suspend on a request to its caller
+///
+/// When the body awaits [`Asker::ask`], [`AskStateMachine::exec_step`] returns
+/// [`Step::Request`] with the request, and the caller answers it with
+/// [`AskStateMachine::provide`] before polling again. This inverts control for hardware
+/// access: the machine describes what it needs, and the caller (or a test script) performs it.
+///
+/// ```
+/// use async_state_machine_example::{AskStateMachine, Asker, Step};
+/// use core::pin::pin;
+///
+/// struct ReadRegister(u8);
+///
+/// async fn read_id(bus: &Asker<ReadRegister, u8>) -> u16 {
+///     let hi = bus.ask(ReadRegister(0x0f)).await;
+///     let lo = bus.ask(ReadRegister(0x10)).await;
+///     u16::from_be_bytes([hi, lo])
+/// }
+///
+/// let asker = Asker::new();
+/// let fut = pin!(read_id(&asker));
+/// let mut fsm = AskStateMachine::new(fut, &asker);
+/// let result = loop {
+///     match fsm.exec_step() {
+///         Step::Request(ReadRegister(addr)) => fsm.provide(addr + 1),
+///         Step::Ready(id) => break id,
+///         _ => (),
+///     }
+/// };
+/// assert_eq!(result, 0x1011);
+/// ```
+pub struct AskStateMachine<'a, 'q, F, Q, A>
+where
+    F: Future
+{
+    machine: AsyncStateMachine<'a, F, F::Output>,
+    asker: &'q Asker<Q, A>,
+}
+
+impl<'a, 'q, F, Q, A> AskStateMachine<'a, 'q, F, Q, A>
+where
+    F: Future
+{
+    /// Create a machine from a pinned future, and the asker the future makes requests through
+    pub fn new(fut: Pin<&'a mut F>, asker: &'q Asker<Q, A>) -> Self {
+        Self { machine: AsyncStateMachine::new(fut), asker }
+    }
+
+    /// Poll the future one time
+    ///
+    /// Returns [`Step::Request`] if the body made a request during this poll.
+    pub fn exec_step(&mut self) -> Step<F::Output, Q> {
+        let step = self.machine.exec_step();
+        if let Some(request) = self.asker.request.take() {
+            return Step::Request(request);
+        }
+        match step {
+            Step::Pending => Step::Pending,
+            Step::Stalled => Step::Stalled,
+            Step::Skipped => Step::Skipped,
+            Step::Ready(result) => Step::Ready(result),
+        }
+    }
+
+    /// Answer the outstanding request, ready for the next poll
+    pub fn provide(&mut self, answer: A) {
+        self.asker.answer.set(Some(answer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::pin::pin;
+
+    #[test]
+    fn test_unanswered_request_stalls() {
+        let asker = Asker::new();
+        let fut = pin!(async { asker.ask("ping").await });
+        let mut fsm = AskStateMachine::new(fut, &asker);
+
+        assert_eq!(fsm.exec_step(), Step::Request("ping"));
+        assert_eq!(fsm.exec_step(), Step::Stalled);
+        fsm.provide("pong");
+        assert_eq!(fsm.exec_step(), Step::Ready("pong"));
+    }
+}
@@ -0,0 +1,239 @@
+//! Small atomic cells for sharing state between machines, threads and interrupt handlers
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A boolean flag which can be shared between contexts
+///
+/// On targets without compare-and-swap (e.g. Cortex-M0), [`AtomicFlag::take`] is implemented with
+/// a separate load and store. The only effect of that is that a `set` which lands between the two
+/// is merged with the one being taken, which is how a flag behaves anyway.
+#[derive(Debug, Default)]
+pub struct AtomicFlag {
+    value: AtomicBool,
+}
+
+impl AtomicFlag {
+    pub const fn new(value: bool) -> Self {
+        Self { value: AtomicBool::new(value) }
+    }
+
+    pub fn get(&self) -> bool {
+        self.value.load(Ordering::Acquire)
+    }
+
+    pub fn store(&self, value: bool) {
+        self.value.store(value, Ordering::Release);
+    }
+
+    pub fn set(&self) {
+        self.store(true);
+    }
+
+    pub fn clear(&self) {
+        self.store(false);
+    }
+
+    /// Clear the flag, returning whether it was set
+    pub fn take(&self) -> bool {
+        #[cfg(target_has_atomic = "8")]
+        {
+            self.value.swap(false, Ordering::AcqRel)
+        }
+        #[cfg(not(target_has_atomic = "8"))]
+        {
+            let value = self.get();
+            if value {
+                self.clear();
+            }
+            value
+        }
+    }
+}
+
+pub use option::AtomicOption;
+
+#[cfg(target_has_atomic = "8")]
+mod option {
+    use core::cell::UnsafeCell;
+    use core::sync::atomic::{AtomicU8, Ordering};
+
+    const EMPTY: u8 = 0;
+    const FULL: u8 = 1;
+    const LOCKED: u8 = 2;
+
+    /// An optional `Copy` value which can be shared between contexts
+    ///
+    /// Each operation briefly locks the cell while the value is copied in or out. Contending
+    /// accesses spin until the lock is released, so this must not be used between an interrupt
+    /// handler and code it can preempt on the same core, since the handler would spin forever if
+    /// it interrupted the lock holder.
+    ///
+    /// On targets without compare-and-swap, each operation runs inside a [`critical_section`]
+    /// instead, which may also be used between an interrupt handler and the code it preempts.
+    /// Single-threaded systems can use [`LocalOption`](crate::LocalOption) instead.
+    pub struct AtomicOption<T: Copy> {
+        state: AtomicU8,
+        value: UnsafeCell<Option<T>>,
+    }
+
+    // SAFETY: The value is only accessed while the state is held at LOCKED, which only one context
+    // can do at a time
+    unsafe impl<T: Copy + Send> Sync for AtomicOption<T> {}
+
+    impl<T: Copy> AtomicOption<T> {
+        pub const fn new(value: Option<T>) -> Self {
+            let state = if value.is_some() { FULL } else { EMPTY };
+            Self { state: AtomicU8::new(state), value: UnsafeCell::new(value) }
+        }
+
+        /// Lock the cell, pass the value to `f`, then unlock it
+        fn with<R>(&self, f: impl FnOnce(&mut Option<T>) -> R) -> R {
+            loop {
+                let state = self.state.load(Ordering::Relaxed);
+                if state != LOCKED
+                    && self
+                        .state
+                        .compare_exchange_weak(state, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+            // SAFETY: The state is LOCKED, so no other context can access the value
+            let value = unsafe { &mut *self.value.get() };
+            let result = f(value);
+            let state = if value.is_some() { FULL } else { EMPTY };
+            self.state.store(state, Ordering::Release);
+            result
+        }
+
+        /// Return a copy of the value
+        pub fn load(&self) -> Option<T> {
+            // Avoid taking the lock for the common case where there is nothing to read
+            if self.state.load(Ordering::Acquire) == EMPTY {
+                return None;
+            }
+            self.with(|value| *value)
+        }
+
+        pub fn store(&self, value: Option<T>) {
+            self.with(|v| *v = value);
+        }
+
+        /// Take the value, leaving the cell empty
+        pub fn take(&self) -> Option<T> {
+            if self.state.load(Ordering::Acquire) == EMPTY {
+                return None;
+            }
+            self.with(|value| value.take())
+        }
+
+        /// Store `value`, returning the previous value
+        pub fn replace(&self, value: Option<T>) -> Option<T> {
+            self.with(|v| core::mem::replace(v, value))
+        }
+    }
+
+    impl<T: Copy> Default for AtomicOption<T> {
+        fn default() -> Self {
+            Self::new(None)
+        }
+    }
+
+    impl<T: Copy + core::fmt::Debug> core::fmt::Debug for AtomicOption<T> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_tuple("AtomicOption").field(&self.load()).finish()
+        }
+    }
+}
+
+#[cfg(not(target_has_atomic = "8"))]
+mod option {
+    use core::cell::Cell;
+
+    use critical_section::Mutex;
+
+    /// An optional `Copy` value which can be shared between contexts
+    ///
+    /// This target has no compare-and-swap, so each operation runs inside a
+    /// [`critical_section`], and the application must provide a critical section implementation.
+    pub struct AtomicOption<T: Copy> {
+        value: Mutex<Cell<Option<T>>>,
+    }
+
+    impl<T: Copy> AtomicOption<T> {
+        pub const fn new(value: Option<T>) -> Self {
+            Self { value: Mutex::new(Cell::new(value)) }
+        }
+
+        /// Return a copy of the value
+        pub fn load(&self) -> Option<T> {
+            critical_section::with(|cs| self.value.borrow(cs).get())
+        }
+
+        pub fn store(&self, value: Option<T>) {
+            critical_section::with(|cs| self.value.borrow(cs).set(value));
+        }
+
+        /// Take the value, leaving the cell empty
+        pub fn take(&self) -> Option<T> {
+            critical_section::with(|cs| self.value.borrow(cs).take())
+        }
+
+        /// Store `value`, returning the previous value
+        pub fn replace(&self, value: Option<T>) -> Option<T> {
+            critical_section::with(|cs| self.value.borrow(cs).replace(value))
+        }
+    }
+
+    impl<T: Copy> Default for AtomicOption<T> {
+        fn default() -> Self {
+            Self::new(None)
+        }
+    }
+
+    impl<T: Copy + core::fmt::Debug> core::fmt::Debug for AtomicOption<T> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_tuple("AtomicOption").field(&self.load()).finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_atomic_flag_take() {
+        let flag = AtomicFlag::new(false);
+        assert!(!flag.take());
+        flag.set();
+        assert!(flag.take());
+        assert!(!flag.get());
+    }
+
+    #[test]
+    fn test_atomic_option_across_threads() {
+        let cell = Arc::new(AtomicOption::<(u32, u32)>::default());
+        let writer = {
+            let cell = cell.clone();
+            std::thread::spawn(move || {
+                for i in 0..10_000 {
+                    cell.store(Some((i, i)));
+                }
+            })
+        };
+        // Values must never be torn
+        while !writer.is_finished() {
+            if let Some((a, b)) = cell.take() {
+                assert_eq!(a, b);
+            }
+        }
+        writer.join().unwrap();
+        cell.store(Some((1, 1)));
+        assert_eq!(cell.replace(None), Some((1, 1)));
+        assert_eq!(cell.load(), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! State machines which own their future on the heap
+//!
+//! These mirror `futures`' `LocalBoxFuture` and `BoxFuture`: [`LocalBoxStateMachine`] accepts any
+//! future, while [`SendBoxStateMachine`] only accepts `Send` futures and is itself `Send`, so
+//! that APIs can state their thread-safety requirements in their types.
+
+use alloc::boxed::Box;
+use core::pin::Pin;
+
+use crate::{AlreadyFinished, Machine, Step, Stepper};
+
+/// A state machine which owns its future, pinned in a `Box`
+///
+/// Unlike [`AsyncStateMachine`](crate::AsyncStateMachine), this does not borrow the future, so it
+/// can be stored in a struct or returned from a function without the caller having to pin
+/// anything. The cost is a heap allocation, and the future's type is erased.
+pub struct LocalBoxStateMachine<'a, T> {
+    fut: Pin<Box<dyn Future<Output = T> + 'a>>,
+    stepper: Stepper,
+}
+
+impl<'a, T> LocalBoxStateMachine<'a, T> {
+    /// Move `fut` to the heap, and wrap it in a state machine
+    pub fn new(fut: impl Future<Output = T> + 'a) -> Self {
+        Self { fut: Box::pin(fut), stepper: Stepper::new() }
+    }
+
+    /// Poll the future one time, see [`AsyncStateMachine::exec`](crate::AsyncStateMachine::exec)
+    ///
+    /// Once the future has completed it is not polled again, and every later call returns None.
+    pub fn exec(&mut self) -> Option<T> {
+        self.try_exec().ok()?.ready()
+    }
+
+    /// Poll the future one time, failing if it has already completed
+    pub fn try_exec(&mut self) -> Result<Step<T>, AlreadyFinished> {
+        self.stepper.try_exec(self.fut.as_mut())
+    }
+
+    /// Poll the future one time, with a finished machine reported as [`Step::Stalled`]
+    pub fn exec_step(&mut self) -> Step<T> {
+        self.try_exec().unwrap_or(Step::Stalled)
+    }
+
+    /// True once the future has completed
+    pub fn is_finished(&self) -> bool {
+        self.stepper.finished
+    }
+}
+
+/// A `Send` state machine which owns its future, pinned in a `Box`
+///
+/// This is the same as [`LocalBoxStateMachine`], but requires the future to be `Send`, so the
+/// machine can be moved to (or created for) another thread.
+pub struct SendBoxStateMachine<'a, T> {
+    fut: Pin<Box<dyn Future<Output = T> + Send + 'a>>,
+    stepper: Stepper,
+}
+
+impl<'a, T> SendBoxStateMachine<'a, T> {
+    /// Move `fut` to the heap, and wrap it in a state machine
+    pub fn new(fut: impl Future<Output = T> + Send + 'a) -> Self {
+        Self { fut: Box::pin(fut), stepper: Stepper::new() }
+    }
+
+    /// Poll the future one time, see [`AsyncStateMachine::exec`](crate::AsyncStateMachine::exec)
+    ///
+    /// Once the future has completed it is not polled again, and every later call returns None.
+    pub fn exec(&mut self) -> Option<T> {
+        self.try_exec().ok()?.ready()
+    }
+
+    /// Poll the future one time, failing if it has already completed
+    pub fn try_exec(&mut self) -> Result<Step<T>, AlreadyFinished> {
+        self.stepper.try_exec(self.fut.as_mut())
+    }
+
+    /// Poll the future one time, with a finished machine reported as [`Step::Stalled`]
+    pub fn exec_step(&mut self) -> Step<T> {
+        self.try_exec().unwrap_or(Step::Stalled)
+    }
+
+    /// True once the future has completed
+    pub fn is_finished(&self) -> bool {
+        self.stepper.finished
+    }
+
+    /// Drop the `Send` guarantee, e.g. to store this alongside local machines
+    pub fn into_local(self) -> LocalBoxStateMachine<'a, T> {
+        LocalBoxStateMachine { fut: self.fut, stepper: self.stepper }
+    }
+}
+
+impl<'a, T> From<SendBoxStateMachine<'a, T>> for LocalBoxStateMachine<'a, T> {
+    fn from(fsm: SendBoxStateMachine<'a, T>) -> Self {
+        fsm.into_local()
+    }
+}
+
+impl<T> Machine for LocalBoxStateMachine<'_, T> {
+    type Output = T;
+
+    fn exec_step(&mut self) -> Step<T> {
+        LocalBoxStateMachine::exec_step(self)
+    }
+}
+
+impl<T> Machine for SendBoxStateMachine<'_, T> {
+    type Output = T;
+
+    fn exec_step(&mut self) -> Step<T> {
+        SendBoxStateMachine::exec_step(self)
+    }
+}
+
+/// Create a [`LocalBoxStateMachine`] from a future in one expression
+///
+/// Prefix the future with `send` to create a [`SendBoxStateMachine`] instead.
+///
+/// ```
+/// use async_state_machine_example::heap_machine;
+///
+/// let mut fsm = heap_machine!(async {
+///     futures::pending!();
+///     42
+/// });
+/// assert_eq!(fsm.exec(), None);
+/// assert_eq!(fsm.exec(), Some(42));
+///
+/// let fsm = heap_machine!(send async { 1 });
+/// std::thread::spawn(move || {
+///     let mut fsm = fsm;
+///     assert_eq!(fsm.exec(), Some(1));
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+#[macro_export]
+macro_rules! heap_machine {
+    (send $fut:expr) => {
+        $crate::SendBoxStateMachine::new($fut)
+    };
+    ($fut:expr) => {
+        $crate::LocalBoxStateMachine::new($fut)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::pending;
+
+    #[test]
+    fn test_send_into_local() {
+        let fsm = SendBoxStateMachine::new(async {
+            pending!();
+            5
+        });
+        let mut machines: Vec<LocalBoxStateMachine<'_, u32>> = vec![
+            LocalBoxStateMachine::new(async { 4 }),
+            fsm.into(),
+        ];
+        let results: Vec<_> = machines.iter_mut().map(|m| m.exec()).collect();
+        assert_eq!(results, [Some(4), None]);
+        assert_eq!(machines[1].exec(), Some(5));
+    }
+
+    #[test]
+    fn test_exec_after_completion() {
+        let mut local = LocalBoxStateMachine::new(async { 1 });
+        assert_eq!(local.exec(), Some(1));
+        assert_eq!(local.exec(), None);
+        assert_eq!(local.try_exec(), Err(AlreadyFinished));
+
+        let mut send = SendBoxStateMachine::new(async { 2 });
+        assert_eq!(send.exec_step(), Step::Ready(2));
+        assert!(send.is_finished());
+        assert_eq!(send.exec(), None);
+    }
+}
@@ -0,0 +1,44 @@
+//! Cooperative cancellation of a machine
+
+use core::cell::Cell;
+
+use crate::yield_now;
+
+/// A request for a machine to stop, which the machine checks for itself
+///
+/// Unlike dropping the machine, cancelling it lets it finish on its own terms at a point of its
+/// choosing, e.g. after putting the hardware it drives into a safe state.
+///
+/// ```ignore
+/// async fn heater(cancel: &CancelToken) {
+///     heater_on();
+///     cancel.cancelled().await;
+///     heater_off();
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: Cell<bool>,
+}
+
+impl CancelToken {
+    pub const fn new() -> Self {
+        Self { cancelled: Cell::new(false) }
+    }
+
+    /// Ask the machine to stop
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+
+    /// Wait until the machine has been asked to stop
+    pub async fn cancelled(&self) {
+        while !self.is_cancelled() {
+            yield_now().await
+        }
+    }
+}
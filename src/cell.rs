@@ -0,0 +1,73 @@
+//! Non-atomic equivalents of the shared cells, for strictly single-threaded systems
+//!
+//! These have the same interfaces as [`AtomicFlag`](crate::AtomicFlag) and
+//! [`AtomicOption`](crate::AtomicOption), but are built on `Cell`, so they cost nothing extra on
+//! targets where atomic read-modify-write operations are emulated or unavailable. They are not
+//! `Sync`, so the compiler will reject any attempt to share them with another thread or an
+//! interrupt handler.
+
+use core::cell::Cell;
+
+/// A boolean flag shared between machines on one thread
+#[derive(Debug, Default)]
+pub struct LocalFlag {
+    value: Cell<bool>,
+}
+
+impl LocalFlag {
+    pub const fn new(value: bool) -> Self {
+        Self { value: Cell::new(value) }
+    }
+
+    pub fn get(&self) -> bool {
+        self.value.get()
+    }
+
+    pub fn store(&self, value: bool) {
+        self.value.set(value);
+    }
+
+    pub fn set(&self) {
+        self.store(true);
+    }
+
+    pub fn clear(&self) {
+        self.store(false);
+    }
+
+    /// Clear the flag, returning whether it was set
+    pub fn take(&self) -> bool {
+        self.value.replace(false)
+    }
+}
+
+/// An optional `Copy` value shared between machines on one thread
+#[derive(Debug, Default)]
+pub struct LocalOption<T: Copy> {
+    value: Cell<Option<T>>,
+}
+
+impl<T: Copy> LocalOption<T> {
+    pub const fn new(value: Option<T>) -> Self {
+        Self { value: Cell::new(value) }
+    }
+
+    /// Return a copy of the value
+    pub fn load(&self) -> Option<T> {
+        self.value.get()
+    }
+
+    pub fn store(&self, value: Option<T>) {
+        self.value.set(value);
+    }
+
+    /// Take the value, leaving the cell empty
+    pub fn take(&self) -> Option<T> {
+        self.value.take()
+    }
+
+    /// Store `value`, returning the previous value
+    pub fn replace(&self, value: Option<T>) -> Option<T> {
+        self.value.replace(value)
+    }
+}
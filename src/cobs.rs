@@ -0,0 +1,91 @@
+//! Consistent Overhead Byte Stuffing, for delimiting frames on a byte stream
+
+use crate::{ByteSink, Stage};
+
+/// A [`Stage`] which COBS-encodes the stream as one packet, terminated by a zero byte
+///
+/// The encoded packet contains no zeros, so the receiver can find packet boundaries by looking
+/// for the terminator, and resynchronise after losing bytes. The overhead is one byte for every
+/// 254 bytes of input, rounded up, with a minimum of one, plus the terminator.
+pub struct Cobs {
+    block: [u8; 254],
+    len: usize,
+    /// True if the last block flushed was a full one, with no zero following it
+    after_full_block: bool,
+}
+
+impl Cobs {
+    pub const fn new() -> Self {
+        Self { block: [0; 254], len: 0, after_full_block: false }
+    }
+
+    async fn flush_block(&mut self, out: &mut impl ByteSink) {
+        out.write(&[self.len as u8 + 1]).await;
+        out.write(&self.block[..self.len]).await;
+        self.after_full_block = self.len == self.block.len();
+        self.len = 0;
+    }
+}
+
+impl Default for Cobs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for Cobs {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &b in bytes {
+            if b == 0 {
+                self.flush_block(out).await;
+            } else {
+                self.block[self.len] = b;
+                self.len += 1;
+                if self.len == self.block.len() {
+                    // A full block is not followed by an implicit zero
+                    self.flush_block(out).await;
+                }
+            }
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        // A packet ending with a full block needs no empty block after it, since the full block
+        // already implies no trailing zero
+        if self.len > 0 || !self.after_full_block {
+            self.flush_block(out).await;
+        }
+        self.after_full_block = false;
+        out.write(&[0]).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(input: &[u8]) -> Vec<u8> {
+        crate::stage::run(Cobs::new(), input, input.len().max(1)).1
+    }
+
+    #[test]
+    fn test_cobs() {
+        assert_eq!(encode(&[]), [1, 0]);
+        assert_eq!(encode(&[0]), [1, 1, 0]);
+        assert_eq!(encode(&[0x11, 0x22, 0x00, 0x33]), [3, 0x11, 0x22, 2, 0x33, 0]);
+
+        let full: Vec<u8> = (1..=254).collect();
+        let encoded = encode(&full);
+        assert_eq!(encoded.len(), 256);
+        assert_eq!(encoded[0], 0xff);
+        assert_eq!(encoded[255], 0);
+        let mut full_then_zero = full.clone();
+        full_then_zero.push(0);
+        assert_eq!(encode(&full_then_zero)[255..], [1, 1, 0]);
+
+        let long: Vec<u8> = (1..=255).collect();
+        let encoded = encode(&long);
+        assert_eq!(encoded[0], 0xff);
+        assert_eq!(encoded[255..], [2, 255, 0]);
+    }
+}
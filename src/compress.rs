@@ -0,0 +1,330 @@
+//! A small LZ77 compression stage, with bounded memory use
+//!
+//! The format is specific to this crate, and unversioned: it is not compatible with heatshrink,
+//! LZ4 or any other tool, and may change between releases. Data compressed with it should only
+//! be decompressed by the same version of the crate.
+
+use crate::{ByteSink, Stage};
+
+/// How far back a match can refer
+const WINDOW: usize = 256;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+const MAX_LITERALS: usize = 0x80;
+/// The number of hash chain buckets, each holding the most recent position with that hash
+const HASH_SIZE: usize = 64;
+/// How many earlier positions with the same hash are tried per match search
+const MAX_PROBES: usize = 8;
+
+/// Error from [`Decompress`] for input which was not produced by [`Compress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptInput;
+
+impl core::fmt::Display for CorruptInput {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("corrupt compressed input")
+    }
+}
+
+impl core::error::Error for CorruptInput {}
+
+/// A [`Stage`] which compresses the stream with LZ77 over a 256 byte window
+///
+/// The output is a sequence of tokens. A token byte below `0x80` is followed by that many plus
+/// one literal bytes. A token byte of `0x80` or above is followed by an offset byte, and repeats
+/// `token - 0x80 + 3` bytes starting `offset + 1` bytes back in the output. This suits the short,
+/// repetitive records typically persisted to flash, and needs under 1kB of state on either side.
+///
+/// Matches are found through hash chains over the first three bytes of each position, trying at
+/// most a few earlier positions per byte, so compressing costs a bounded number of comparisons
+/// per input byte rather than a scan of the whole window.
+pub struct Compress {
+    /// The history the encoder can match against, followed by the lookahead still to be encoded
+    buf: [u8; WINDOW + MAX_MATCH],
+    history: usize,
+    lookahead: usize,
+    /// The position in the stream of the start of the lookahead
+    position: usize,
+    /// The most recent position with each hash, truncated to 16 bits
+    head: [u16; HASH_SIZE],
+    /// For each position in the window, how far back the previous one with the same hash is, or
+    /// 0 if there is none within reach
+    prev: [u8; WINDOW],
+    literals: [u8; MAX_LITERALS],
+    literal_count: usize,
+}
+
+impl Compress {
+    /// Create a compressor with an empty window
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; WINDOW + MAX_MATCH],
+            history: 0,
+            lookahead: 0,
+            position: 0,
+            head: [0; HASH_SIZE],
+            prev: [0; WINDOW],
+            literals: [0; MAX_LITERALS],
+            literal_count: 0,
+        }
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let [a, b, c] = [0, 1, 2].map(|k| usize::from(self.buf[i + k]));
+        ((a * 31 + b) * 31 + c) % HASH_SIZE
+    }
+
+    /// Find the longest match for the start of the lookahead, returning its length and distance
+    ///
+    /// Chain entries are only hints: positions are truncated, so an entry may be stale, but every
+    /// candidate is compared against the buffer before it is used.
+    fn find_match(&self) -> (usize, usize) {
+        let target = self.history;
+        let (mut best_len, mut best_dist) = (0, 0);
+        if self.lookahead < MIN_MATCH {
+            return (best_len, best_dist);
+        }
+        let mut candidate = self.head[self.hash(target)];
+        for _ in 0..MAX_PROBES {
+            let dist = usize::from((self.position as u16).wrapping_sub(candidate));
+            if dist == 0 || dist > self.history {
+                break;
+            }
+            // A match may run on into the lookahead, which the decoder reproduces by copying
+            // forwards one byte at a time
+            let start = target - dist;
+            let len = (0..self.lookahead)
+                .take_while(|&k| self.buf[start + k] == self.buf[target + k])
+                .count();
+            if len > best_len {
+                (best_len, best_dist) = (len, dist);
+            }
+            match self.prev[usize::from(candidate) % WINDOW] {
+                0 => break,
+                back => candidate = candidate.wrapping_sub(u16::from(back)),
+            }
+        }
+        (best_len, best_dist)
+    }
+
+    /// Add the position at buffer index `i` to the hash chains, if its first three bytes are known
+    fn insert(&mut self, i: usize, position: usize) {
+        if i + MIN_MATCH > self.history + self.lookahead {
+            return;
+        }
+        let hash = self.hash(i);
+        let back = (position as u16).wrapping_sub(self.head[hash]);
+        self.prev[position % WINDOW] = u8::try_from(back).unwrap_or(0);
+        self.head[hash] = position as u16;
+    }
+
+    /// Encode the start of the lookahead, as a match if one is long enough or else as a literal
+    async fn step(&mut self, out: &mut impl ByteSink) {
+        let target = self.history;
+        let (best_len, best_dist) = self.find_match();
+
+        let consumed = if best_len >= MIN_MATCH {
+            self.flush_literals(out).await;
+            let token = 0x80 | (best_len - MIN_MATCH) as u8;
+            out.write(&[token, (best_dist - 1) as u8]).await;
+            best_len
+        } else {
+            self.literals[self.literal_count] = self.buf[target];
+            self.literal_count += 1;
+            if self.literal_count == MAX_LITERALS {
+                self.flush_literals(out).await;
+            }
+            1
+        };
+
+        for k in 0..consumed {
+            self.insert(target + k, self.position + k);
+        }
+        self.position += consumed;
+        self.history += consumed;
+        self.lookahead -= consumed;
+        if self.history > WINDOW {
+            let drop = self.history - WINDOW;
+            self.buf.copy_within(drop..self.history + self.lookahead, 0);
+            self.history = WINDOW;
+        }
+    }
+
+    async fn flush_literals(&mut self, out: &mut impl ByteSink) {
+        if self.literal_count > 0 {
+            out.write(&[self.literal_count as u8 - 1]).await;
+            out.write(&self.literals[..self.literal_count]).await;
+            self.literal_count = 0;
+        }
+    }
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for Compress {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &b in bytes {
+            self.buf[self.history + self.lookahead] = b;
+            self.lookahead += 1;
+            if self.lookahead == MAX_MATCH {
+                self.step(out).await;
+            }
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        while self.lookahead > 0 {
+            self.step(out).await;
+        }
+        self.flush_literals(out).await;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum DecodeState {
+    Token,
+    Literals(usize),
+    Offset(usize),
+}
+
+/// A [`Stage`] which reverses [`Compress`]
+///
+/// Corrupt input can't be reported through the stream, so the first error is recorded, after
+/// which all further input is discarded. Check [`Decompress::error`] once the stream is finished.
+pub struct Decompress {
+    history: [u8; WINDOW],
+    written: usize,
+    state: DecodeState,
+    error: Option<CorruptInput>,
+}
+
+impl Decompress {
+    /// Create a decompressor with an empty window
+    pub const fn new() -> Self {
+        Self { history: [0; WINDOW], written: 0, state: DecodeState::Token, error: None }
+    }
+
+    /// The error which stopped decompression, if any
+    pub fn error(&self) -> Option<CorruptInput> {
+        self.error
+    }
+
+    fn push_history(&mut self, b: u8) {
+        self.history[self.written % WINDOW] = b;
+        self.written += 1;
+    }
+}
+
+impl Default for Decompress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for Decompress {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        for &b in bytes {
+            if self.error.is_some() {
+                return;
+            }
+            self.state = match self.state {
+                DecodeState::Token if b & 0x80 != 0 => {
+                    DecodeState::Offset((b & 0x7f) as usize + MIN_MATCH)
+                }
+                DecodeState::Token => DecodeState::Literals(b as usize + 1),
+                DecodeState::Literals(remaining) => {
+                    self.push_history(b);
+                    out.write(&[b]).await;
+                    match remaining - 1 {
+                        0 => DecodeState::Token,
+                        n => DecodeState::Literals(n),
+                    }
+                }
+                DecodeState::Offset(len) => {
+                    let offset = b as usize + 1;
+                    if offset > self.written {
+                        self.error = Some(CorruptInput);
+                        return;
+                    }
+                    let mut chunk = [0; MAX_MATCH];
+                    for byte in &mut chunk[..len] {
+                        *byte = self.history[(self.written - offset) % WINDOW];
+                        self.push_history(*byte);
+                    }
+                    out.write(&chunk[..len]).await;
+                    DecodeState::Token
+                }
+            };
+        }
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        let _ = out;
+        if !matches!(self.state, DecodeState::Token) {
+            self.error.get_or_insert(CorruptInput);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stage::run;
+
+    #[test]
+    fn test_round_trip() {
+        let mut records = Vec::new();
+        for i in 0..100u32 {
+            records.extend_from_slice(b"temp=");
+            records.extend_from_slice(&(i / 10).to_le_bytes());
+            records.push((i * 37) as u8);
+        }
+        for chunk in [1, 7, 1000] {
+            let (_, compressed) = run(Compress::new(), &records, chunk);
+            assert!(compressed.len() < records.len() / 2);
+            let (decompress, output) = run(Decompress::new(), &compressed, chunk);
+            assert_eq!(decompress.error(), None);
+            assert_eq!(output, records);
+        }
+    }
+
+    #[test]
+    fn test_long_stream_round_trip() {
+        // Long enough for the truncated positions in the hash chains to wrap, with repeats both
+        // near and beyond the window
+        let mut state = 1u32;
+        let mut input = Vec::new();
+        while input.len() < 150_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let back = (state >> 8) as usize % 400;
+            if back < input.len() && back > 0 {
+                let start = input.len() - back;
+                for k in 0..(state >> 20) as usize % 40 {
+                    input.push(input[start + k % back]);
+                }
+            } else {
+                input.push((state >> 16) as u8);
+            }
+        }
+        let (_, compressed) = run(Compress::new(), &input, 1000);
+        assert!(compressed.len() < input.len());
+        let (decompress, output) = run(Decompress::new(), &compressed, 1000);
+        assert_eq!(decompress.error(), None);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        // A match before anything has been written
+        let (decompress, output) = run(Decompress::new(), &[0x80, 0], 1);
+        assert_eq!(decompress.error(), Some(CorruptInput));
+        assert!(output.is_empty());
+        // Truncated literals
+        let (decompress, _) = run(Decompress::new(), &[3, 1, 2], 1);
+        assert_eq!(decompress.error(), Some(CorruptInput));
+    }
+}
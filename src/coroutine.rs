@@ -0,0 +1,122 @@
+//! Coroutines: async bodies which hand values back to their caller at each suspension
+//!
+//! This generalizes the pattern used by the serializer example, where the async body writes to a
+//! shared register and then yields, so that the caller can pick up the value between polls.
+
+use core::cell::Cell;
+use core::pin::Pin;
+
+use crate::poll_once;
+use crate::yield_now;
+
+/// The slot through which a coroutine body passes yielded values to its [`Coroutine`]
+pub struct Yielder<Y> {
+    slot: Cell<Option<Y>>,
+}
+
+impl<Y> Yielder<Y> {
+    pub const fn new() -> Self {
+        Self { slot: Cell::new(None) }
+    }
+
+    /// Hand `value` to the caller and suspend until the coroutine is next resumed
+    pub async fn yield_value(&self, value: Y) {
+        self.slot.set(Some(value));
+        yield_now().await
+    }
+
+    fn take(&self) -> Option<Y> {
+        self.slot.take()
+    }
+}
+
+impl<Y> Default for Yielder<Y> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of resuming a [`Coroutine`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineState<Y, R> {
+    /// The body yielded a value and is suspended
+    Yielded(Y),
+    /// The body is suspended on something other than a yield, e.g. waiting for a timer
+    Pending,
+    /// The body has returned
+    Complete(R),
+}
+
+/// Drives an async body which yields values through a [`Yielder`]
+///
+/// Both the future and the yielder are borrowed, so they can be pinned on the stack by the caller:
+///
+/// ```
+/// use async_state_machine_example::{Coroutine, CoroutineState, Yielder};
+/// use core::pin::pin;
+///
+/// async fn count(y: &Yielder<u32>) -> &'static str {
+///     for i in 0..2 {
+///         y.yield_value(i).await;
+///     }
+///     "done"
+/// }
+///
+/// let yielder = Yielder::new();
+/// let body = pin!(count(&yielder));
+/// let mut co = Coroutine::new(body, &yielder);
+/// assert_eq!(co.resume(), CoroutineState::Yielded(0));
+/// assert_eq!(co.resume(), CoroutineState::Yielded(1));
+/// assert_eq!(co.resume(), CoroutineState::Complete("done"));
+/// ```
+pub struct Coroutine<'a, 'y, F, Y>
+where
+    F: Future
+{
+    fut: Pin<&'a mut F>,
+    yielder: &'y Yielder<Y>,
+}
+
+impl<'a, 'y, F, Y> Coroutine<'a, 'y, F, Y>
+where
+    F: Future
+{
+    /// Create a coroutine from a future, and the yielder which the future yields through
+    pub fn new(fut: Pin<&'a mut F>, yielder: &'y Yielder<Y>) -> Self {
+        Self { fut, yielder }
+    }
+
+    /// Poll the body once, returning the value it yielded, if any
+    ///
+    /// The coroutine must not be resumed again after it returns [`CoroutineState::Complete`].
+    pub fn resume(&mut self) -> CoroutineState<Y, F::Output> {
+        match poll_once(self.fut.as_mut()) {
+            Some(result) => CoroutineState::Complete(result),
+            None => match self.yielder.take() {
+                Some(value) => CoroutineState::Yielded(value),
+                None => CoroutineState::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::pin::pin;
+    use futures::pending;
+
+    #[test]
+    fn test_pending_without_yield() {
+        let yielder = Yielder::new();
+        let body = pin!(async {
+            pending!();
+            yielder.yield_value(7).await;
+        });
+        let mut co = Coroutine::new(body, &yielder);
+
+        assert_eq!(co.resume(), CoroutineState::Pending);
+        assert_eq!(co.resume(), CoroutineState::Yielded(7));
+        assert_eq!(co.resume(), CoroutineState::Complete(()));
+    }
+}
@@ -0,0 +1,66 @@
+//! CRC checksums, appended to the stream by a stage
+
+use crate::{ByteSink, Stage};
+
+/// A [`Stage`] which passes the stream through unchanged, followed by its CRC
+///
+/// The CRC is CRC-16/CCITT-FALSE (polynomial `0x1021`, initial value `0xffff`), appended
+/// big-endian, so running the same CRC over the whole output, including the CRC, gives zero.
+pub struct Crc {
+    crc: u16,
+}
+
+impl Crc {
+    pub const fn new() -> Self {
+        Self { crc: 0xffff }
+    }
+
+    /// The CRC of the stream so far
+    pub fn value(&self) -> u16 {
+        self.crc
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.crc ^= (b as u16) << 8;
+            for _ in 0..8 {
+                let carry = self.crc & 0x8000 != 0;
+                self.crc <<= 1;
+                if carry {
+                    self.crc ^= 0x1021;
+                }
+            }
+        }
+    }
+}
+
+impl Default for Crc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for Crc {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        self.update(bytes);
+        out.write(bytes).await;
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        out.write(&self.crc.to_be_bytes()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_value() {
+        let mut crc = Crc::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.value(), 0x29b1);
+        crc.update(&0x29b1u16.to_be_bytes());
+        assert_eq!(crc.value(), 0);
+    }
+}
@@ -0,0 +1,96 @@
+//! Primitives guarded by the `critical-section` crate, for sharing with interrupt handlers
+//!
+//! The plain [`Mailbox`](crate::Mailbox) is `Cell`-based, and can't be shared with an interrupt
+//! handler. The variants here hold their contents in a [`critical_section::Mutex`], so any `Send`
+//! payload, such as a struct of sensor readings, can be exchanged soundly between an ISR and a
+//! polled machine. The application must provide a critical section implementation, e.g. from
+//! `cortex-m` with its `critical-section-single-core` feature, or `critical-section`'s `std`
+//! feature on hosted targets.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+
+use crate::yield_now;
+
+/// A [`Mailbox`](crate::Mailbox) which may be shared with interrupt handlers
+pub struct CsMailbox<T> {
+    slot: Mutex<Cell<Option<T>>>,
+}
+
+impl<T> CsMailbox<T> {
+    pub const fn new() -> Self {
+        Self { slot: Mutex::new(Cell::new(None)) }
+    }
+
+    /// Store `msg` in the mailbox, or hand it back if the mailbox is already full
+    pub fn post(&self, msg: T) -> Result<(), T> {
+        critical_section::with(|cs| {
+            let slot = self.slot.borrow(cs);
+            match slot.take() {
+                Some(existing) => {
+                    slot.set(Some(existing));
+                    Err(msg)
+                }
+                None => {
+                    slot.set(Some(msg));
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    /// Store `msg`, replacing and returning any message which has not been taken yet
+    ///
+    /// This suits ISRs publishing the latest reading, where a stale value is not worth keeping.
+    pub fn replace(&self, msg: T) -> Option<T> {
+        critical_section::with(|cs| self.slot.borrow(cs).replace(Some(msg)))
+    }
+
+    /// Take the message, if there is one
+    pub fn try_take(&self) -> Option<T> {
+        critical_section::with(|cs| self.slot.borrow(cs).take())
+    }
+
+    /// Wait for a message to be posted, and take it
+    pub async fn recv(&self) -> T {
+        loop {
+            if let Some(msg) = self.try_take() {
+                return msg;
+            }
+            yield_now().await
+        }
+    }
+}
+
+impl<T> Default for CsMailbox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll_once;
+    use core::pin::pin;
+
+    #[derive(Debug, PartialEq)]
+    struct Reading {
+        channel: u8,
+        value: i32,
+    }
+
+    #[test]
+    fn test_cs_mailbox_from_thread() {
+        static MAILBOX: CsMailbox<Reading> = CsMailbox::new();
+        let mut fut = pin!(MAILBOX.recv());
+        assert_eq!(poll_once(fut.as_mut()), None);
+
+        // Stand-in for an interrupt handler
+        std::thread::spawn(|| MAILBOX.post(Reading { channel: 2, value: -40 }).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(poll_once(fut.as_mut()), Some(Reading { channel: 2, value: -40 }));
+    }
+}
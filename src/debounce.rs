@@ -0,0 +1,171 @@
+//! Debouncing and throttling of noisy inputs
+
+use core::time::Duration;
+
+use crate::{yield_now, Clock};
+
+/// Wait for a debounced change of a digital input
+///
+/// `input` is sampled on every poll. Its value on the first poll is taken as the current stable
+/// level, and the future completes with the new level once the input has read the opposite value
+/// continuously for at least `stable_for`. Any glitch back to the old level restarts the timer.
+///
+/// Because the input is sampled only when the machine is polled, pulses shorter than the poll
+/// interval may be missed entirely, which is usually what is wanted from a debouncer.
+///
+/// ```ignore
+/// loop {
+///     if debounce(|| button.is_low(), Duration::from_millis(20), &clock).await {
+///         // pressed
+///     } else {
+///         // released
+///     }
+/// }
+/// ```
+pub async fn debounce(
+    mut input: impl FnMut() -> bool,
+    stable_for: Duration,
+    clock: &impl Clock,
+) -> bool {
+    let stable = input();
+    settle(stable, &mut input, stable_for, clock).await
+}
+
+/// Wait for `input` to read the opposite of `stable` continuously for `stable_for`
+async fn settle(
+    stable: bool,
+    input: &mut impl FnMut() -> bool,
+    stable_for: Duration,
+    clock: &impl Clock,
+) -> bool {
+    let mut changed_at = None;
+    loop {
+        let level = input();
+        if level == stable {
+            changed_at = None;
+        } else {
+            let now = clock.now();
+            let since = *changed_at.get_or_insert(now);
+            if now - since >= stable_for {
+                return level;
+            }
+        }
+        yield_now().await
+    }
+}
+
+/// Report every debounced change of a digital input, forever
+///
+/// This is [`debounce`] in a loop, calling `on_change` with each new stable level; the level on
+/// the first poll is taken as the starting point, and not reported. It suits
+/// running one coroutine per key of a keypad or per button, with `on_change` pushing to an
+/// [`EventQueue`](crate::EventQueue). The future never completes.
+///
+/// ```ignore
+/// let key = watch_debounced(|| scan.get() & bit != 0, DEBOUNCE, &clock, |pressed| {
+///     events.push(KeyEvent { key, pressed }).ok();
+/// });
+/// ```
+pub async fn watch_debounced(
+    mut input: impl FnMut() -> bool,
+    stable_for: Duration,
+    clock: &impl Clock,
+    mut on_change: impl FnMut(bool),
+) {
+    let mut stable = input();
+    loop {
+        stable = settle(stable, &mut input, stable_for, clock).await;
+        on_change(stable);
+    }
+}
+
+/// Limits how often an input is allowed to trigger
+///
+/// Each call to [`Throttle::next`] waits for `input` to read true, but never completes sooner
+/// than `interval` after the previous call completed. Triggers which arrive during the hold-off
+/// are ignored, rather than queued.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Duration>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last: None }
+    }
+
+    /// Wait for the next accepted trigger of `input`
+    pub async fn next(&mut self, mut input: impl FnMut() -> bool, clock: &impl Clock) {
+        loop {
+            let now = clock.now();
+            let held_off = self.last.is_some_and(|last| now - last < self.interval);
+            if !held_off && input() {
+                self.last = Some(now);
+                return;
+            }
+            yield_now().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{poll_once, MockClock};
+    use core::cell::Cell;
+    use core::pin::pin;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_debounce_ignores_glitches() {
+        let clock = MockClock::new();
+        let pin_state = Cell::new(false);
+        let mut fut = pin!(debounce(|| pin_state.get(), Duration::from_millis(10), &clock));
+
+        assert_eq!(poll_once(fut.as_mut()), None);
+        // A short glitch high
+        pin_state.set(true);
+        assert_eq!(poll_once(fut.as_mut()), None);
+        clock.advance(Duration::from_millis(5));
+        pin_state.set(false);
+        assert_eq!(poll_once(fut.as_mut()), None);
+        // A real press
+        pin_state.set(true);
+        assert_eq!(poll_once(fut.as_mut()), None);
+        clock.advance(Duration::from_millis(9));
+        assert_eq!(poll_once(fut.as_mut()), None);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(poll_once(fut.as_mut()), Some(true));
+    }
+
+    #[test]
+    fn test_watch_reports_each_change_once() {
+        let clock = MockClock::new();
+        let pin_state = Cell::new(false);
+        let changes = core::cell::RefCell::new(Vec::new());
+        let mut fut = pin!(watch_debounced(|| pin_state.get(), 2 * MS, &clock, |level| {
+            changes.borrow_mut().push(level)
+        }));
+
+        for level in [false, true, true, false, true, true, true, false, false, false] {
+            pin_state.set(level);
+            assert_eq!(poll_once(fut.as_mut()), None);
+            clock.advance(MS);
+        }
+        assert_eq!(*changes.borrow(), [true, false]);
+    }
+
+    #[test]
+    fn test_throttle() {
+        let clock = MockClock::new();
+        let mut throttle = Throttle::new(Duration::from_millis(100));
+
+        assert_eq!(poll_once(pin!(throttle.next(|| true, &clock))), Some(()));
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(poll_once(pin!(throttle.next(|| true, &clock))), None);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(poll_once(pin!(throttle.next(|| true, &clock))), Some(()));
+    }
+}
@@ -0,0 +1,157 @@
+//! Waiting for edges on an input pin, signalled from its interrupt handler
+
+use core::cell::{Cell, RefCell};
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use critical_section::Mutex;
+
+/// A transition of a digital input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    /// Either transition; only used to select which edges to wait for
+    Any,
+}
+
+impl Edge {
+    fn mask(self) -> u8 {
+        match self {
+            Edge::Rising => 1,
+            Edge::Falling => 2,
+            Edge::Any => 3,
+        }
+    }
+}
+
+/// Edges on one input, latched by an interrupt handler until a machine waits for them
+///
+/// The pin's ISR calls [`EdgeSignal::trigger`], and a machine awaits
+/// [`EdgeSignal::wait_for_edge`]. Unlike the polled waits in this crate, waiting doesn't wake:
+/// the waker the machine was polled with is stored and woken by the trigger, so with an
+/// [`IdleStrategy`](crate::IdleStrategy) which sleeps until woken, such as `WfeSev`, the machine
+/// is only polled again after the edge. Edges are latched like an interrupt flag, so one which
+/// arrives before the machine starts waiting is not lost; several edges of the same kind merge
+/// into one.
+///
+/// The signal is guarded by the `critical-section` crate, so it can be a `static` shared with the
+/// ISR.
+///
+/// ```
+/// use async_state_machine_example::{poll_once, Edge, EdgeSignal};
+/// use core::pin::pin;
+///
+/// static BUTTON: EdgeSignal = EdgeSignal::new();
+///
+/// let mut fut = pin!(async {
+///     BUTTON.wait_for_edge(Edge::Falling).await;
+///     "pressed"
+/// });
+/// assert_eq!(poll_once(fut.as_mut()), None);
+/// // Called from the pin's interrupt handler
+/// BUTTON.trigger(Edge::Rising);
+/// assert_eq!(poll_once(fut.as_mut()), None);
+/// BUTTON.trigger(Edge::Falling);
+/// assert_eq!(poll_once(fut.as_mut()), Some("pressed"));
+/// ```
+pub struct EdgeSignal {
+    pending: Mutex<Cell<u8>>,
+    waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl EdgeSignal {
+    pub const fn new() -> Self {
+        Self { pending: Mutex::new(Cell::new(0)), waker: Mutex::new(RefCell::new(None)) }
+    }
+
+    /// Record an edge, and wake the machine waiting for edges, if any
+    ///
+    /// `Edge::Any` records both a rising and a falling edge.
+    pub fn trigger(&self, edge: Edge) {
+        let waker = critical_section::with(|cs| {
+            let pending = self.pending.borrow(cs);
+            pending.set(pending.get() | edge.mask());
+            self.waker.borrow_ref_mut(cs).take()
+        });
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Forget any latched edges, e.g. ones from before the input was configured
+    pub fn clear(&self) {
+        critical_section::with(|cs| self.pending.borrow(cs).set(0));
+    }
+
+    /// Wait for an edge matching `edge`, and return which edge it was
+    ///
+    /// Completes immediately if a matching edge is already latched. When waiting for
+    /// [`Edge::Any`] with both latched, the rising edge is returned first. Only one machine
+    /// should wait on a signal at a time, since only the latest waiter's waker is kept.
+    pub fn wait_for_edge(&self, edge: Edge) -> impl Future<Output = Edge> + '_ {
+        WaitForEdge { signal: self, mask: edge.mask() }
+    }
+}
+
+impl Default for EdgeSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`EdgeSignal::wait_for_edge`]
+struct WaitForEdge<'a> {
+    signal: &'a EdgeSignal,
+    mask: u8,
+}
+
+impl Future for WaitForEdge<'_> {
+    type Output = Edge;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Edge> {
+        critical_section::with(|cs| {
+            let pending = self.signal.pending.borrow(cs);
+            let matched = pending.get() & self.mask;
+            let edge = if matched & Edge::Rising.mask() != 0 {
+                Edge::Rising
+            } else if matched != 0 {
+                Edge::Falling
+            } else {
+                let mut waker = self.signal.waker.borrow_ref_mut(cs);
+                if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                    *waker = Some(cx.waker().clone());
+                }
+                return Poll::Pending;
+            };
+            pending.set(pending.get() & !edge.mask());
+            Poll::Ready(edge)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CountingWaker;
+    use core::pin::pin;
+
+    #[test]
+    fn test_wait_is_woken_by_trigger() {
+        let signal = EdgeSignal::new();
+        let waker = CountingWaker::new();
+        let mut cx = Context::from_waker(waker.waker());
+
+        // A latched edge completes the wait straight away
+        signal.trigger(Edge::Falling);
+        let fut = pin!(signal.wait_for_edge(Edge::Any));
+        assert_eq!(fut.poll(&mut cx), Poll::Ready(Edge::Falling));
+
+        let mut fut = pin!(signal.wait_for_edge(Edge::Rising));
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(waker.wake_count(), 0);
+        signal.trigger(Edge::Rising);
+        assert_eq!(waker.wake_count(), 1);
+        assert_eq!(fut.poll(&mut cx), Poll::Ready(Edge::Rising));
+    }
+}
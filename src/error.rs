@@ -0,0 +1,88 @@
+//! A single error type covering every error in the crate
+
+use crate::{
+    AlreadyFinished, AuthFailed, CorruptInput, DemuxError, FrameTooLong, InvalidEncoding,
+    InvalidState, LineTooLong, TimedOut,
+};
+
+/// Any of the crate's errors
+///
+/// Each fallible API returns its own specific error type, which says exactly what can go wrong
+/// there. They all convert into this with `?`, for code which combines several of them and just
+/// needs to pass failures on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    AlreadyFinished,
+    TimedOut,
+    LineTooLong,
+    FrameTooLong,
+    Demux(DemuxError),
+    CorruptInput,
+    AuthFailed,
+    InvalidEncoding,
+    InvalidState,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::AlreadyFinished => AlreadyFinished.fmt(f),
+            Error::TimedOut => TimedOut.fmt(f),
+            Error::LineTooLong => LineTooLong.fmt(f),
+            Error::FrameTooLong => FrameTooLong.fmt(f),
+            Error::Demux(e) => e.fmt(f),
+            Error::CorruptInput => CorruptInput.fmt(f),
+            Error::AuthFailed => AuthFailed.fmt(f),
+            Error::InvalidEncoding => InvalidEncoding.fmt(f),
+            Error::InvalidState => InvalidState.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+macro_rules! impl_from {
+    ($($source:ident => $variant:ident),* $(,)?) => {$(
+        impl From<$source> for Error {
+            fn from(_: $source) -> Self {
+                Error::$variant
+            }
+        }
+    )*};
+}
+
+impl_from!(
+    AlreadyFinished => AlreadyFinished,
+    TimedOut => TimedOut,
+    LineTooLong => LineTooLong,
+    FrameTooLong => FrameTooLong,
+    CorruptInput => CorruptInput,
+    AuthFailed => AuthFailed,
+    InvalidEncoding => InvalidEncoding,
+    InvalidState => InvalidState,
+);
+
+impl From<DemuxError> for Error {
+    fn from(e: DemuxError) -> Self {
+        Error::Demux(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_propagation() {
+        fn parse(frame: Result<(), DemuxError>, line: Result<(), LineTooLong>) -> Result<(), Error> {
+            frame?;
+            line?;
+            Ok(())
+        }
+
+        assert_eq!(parse(Ok(()), Err(LineTooLong)), Err(Error::LineTooLong));
+        let err = parse(Err(DemuxError::UnknownStream(3)), Ok(())).unwrap_err();
+        assert_eq!(err, Error::Demux(DemuxError::UnknownStream(3)));
+        assert_eq!(err.to_string(), "unknown stream 3");
+    }
+}
@@ -0,0 +1,159 @@
+//! Flow control between a producer machine and its consumer
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::yield_now;
+
+/// Credit-based flow control
+///
+/// The consumer [grants](Credits::grant) credits as space becomes available downstream (e.g. a
+/// DMA buffer being drained or a radio freeing a packet slot), and the producer
+/// [acquires](Credits::acquire) credits before emitting data, yielding while there are not
+/// enough. Credits are just a count, so they can stand for bytes, packets, or whatever unit the
+/// downstream is limited by.
+///
+/// ```ignore
+/// async fn producer(credits: &Credits, data: &[u8], tx: &mut impl FnMut(u8)) {
+///     for chunk in data.chunks(16) {
+///         credits.acquire(chunk.len()).await;
+///         chunk.iter().for_each(|&b| tx(b));
+///     }
+/// }
+/// ```
+///
+/// `Credits` is `Sync`, so the consumer side may be an interrupt handler or another thread. If
+/// both sides run on one thread, [`LocalCredits`] does the same job without atomics.
+///
+/// On targets without atomic read-modify-write (e.g. Cortex-M0), updates are made inside a
+/// [`critical_section`], so the application must provide a critical section implementation.
+#[derive(Debug, Default)]
+pub struct Credits {
+    available: AtomicUsize,
+}
+
+impl Credits {
+    /// Create a pool with `initial` credits available
+    pub const fn new(initial: usize) -> Self {
+        Self { available: AtomicUsize::new(initial) }
+    }
+
+    /// Make `n` more credits available to the producer
+    pub fn grant(&self, n: usize) {
+        #[cfg(target_has_atomic = "ptr")]
+        self.available.fetch_add(n, Ordering::Release);
+        #[cfg(not(target_has_atomic = "ptr"))]
+        critical_section::with(|_| {
+            let available = self.available.load(Ordering::Acquire);
+            self.available.store(available + n, Ordering::Release);
+        });
+    }
+
+    /// The number of credits currently available
+    pub fn available(&self) -> usize {
+        self.available.load(Ordering::Acquire)
+    }
+
+    /// Take `n` credits if that many are available, without waiting
+    pub fn try_acquire(&self, n: usize) -> bool {
+        #[cfg(target_has_atomic = "ptr")]
+        {
+            self.available
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |available| {
+                    available.checked_sub(n)
+                })
+                .is_ok()
+        }
+        #[cfg(not(target_has_atomic = "ptr"))]
+        critical_section::with(|_| {
+            let available = self.available.load(Ordering::Acquire);
+            let Some(remaining) = available.checked_sub(n) else {
+                return false;
+            };
+            self.available.store(remaining, Ordering::Release);
+            true
+        })
+    }
+
+    /// Wait until `n` credits are available, and take them
+    ///
+    /// Credits are taken all at once, never partially, so `n` must not be larger than the total
+    /// the consumer will ever have outstanding, or this will wait forever.
+    pub async fn acquire(&self, n: usize) {
+        while !self.try_acquire(n) {
+            yield_now().await
+        }
+    }
+}
+
+/// Non-atomic [`Credits`], for when the producer and consumer run on the same thread
+#[derive(Debug, Default)]
+pub struct LocalCredits {
+    available: Cell<usize>,
+}
+
+impl LocalCredits {
+    /// Create a pool with `initial` credits available
+    pub const fn new(initial: usize) -> Self {
+        Self { available: Cell::new(initial) }
+    }
+
+    /// Make `n` more credits available to the producer
+    pub fn grant(&self, n: usize) {
+        self.available.set(self.available.get() + n);
+    }
+
+    /// The number of credits currently available
+    pub fn available(&self) -> usize {
+        self.available.get()
+    }
+
+    /// Take `n` credits if that many are available, without waiting
+    pub fn try_acquire(&self, n: usize) -> bool {
+        match self.available.get().checked_sub(n) {
+            Some(remaining) => {
+                self.available.set(remaining);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wait until `n` credits are available, and take them
+    ///
+    /// See [`Credits::acquire`].
+    pub async fn acquire(&self, n: usize) {
+        while !self.try_acquire(n) {
+            yield_now().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll_once;
+    use core::pin::pin;
+
+    #[test]
+    fn test_acquire_waits_for_grant() {
+        let credits = Credits::new(3);
+        assert_eq!(poll_once(pin!(credits.acquire(2))), Some(()));
+
+        let mut fut = pin!(credits.acquire(2));
+        assert_eq!(poll_once(fut.as_mut()), None);
+        credits.grant(1);
+        assert_eq!(poll_once(fut.as_mut()), Some(()));
+        assert_eq!(credits.available(), 0);
+    }
+
+    #[test]
+    fn test_local_credits() {
+        let credits = LocalCredits::new(1);
+        let mut fut = pin!(credits.acquire(2));
+        assert_eq!(poll_once(fut.as_mut()), None);
+        credits.grant(1);
+        assert_eq!(poll_once(fut.as_mut()), Some(()));
+        assert!(!credits.try_acquire(1));
+    }
+}
@@ -0,0 +1,184 @@
+//! Pull-based generators which write samples straight into the consumer's buffer
+
+use core::cell::Cell;
+use core::pin::Pin;
+use core::ptr::NonNull;
+
+use futures::pending;
+
+use crate::{poll_once, yield_now, OnExit};
+
+/// The slot through which a [`Generator`] lends its consumer's buffer to the generator body
+///
+/// The buffer is only lent for the duration of [`Generator::fill`], and the body can only reach
+/// it inside the closure passed to [`SliceSlot::write`], which can't hold on to it across an
+/// await. This is what lets the body write samples in place, with no intermediate copy.
+pub struct SliceSlot<T> {
+    /// The unfilled part of the lent buffer
+    buf: Cell<Option<(NonNull<T>, usize)>>,
+}
+
+impl<T> SliceSlot<T> {
+    pub const fn new() -> Self {
+        Self { buf: Cell::new(None) }
+    }
+
+    /// Wait for buffer space, write one block into it, and yield
+    ///
+    /// `f` is given the unfilled part of the consumer's buffer, which is never empty, and
+    /// returns the number of samples it wrote from the start of it. The body picks its own block
+    /// size, and must cope with less space than a whole block. Returns the number written.
+    pub async fn write(&self, f: impl FnOnce(&mut [T]) -> usize) -> usize {
+        let written = loop {
+            match self.buf.take() {
+                Some((ptr, len)) if len > 0 => {
+                    // SAFETY: `Generator::fill` only lends a pointer derived from a live
+                    // `&mut [T]` of at least `len` elements, for the duration of a poll. It is
+                    // taken out of the slot while `f` runs, so the slice can't be aliased.
+                    let buf = unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr(), len) };
+                    let written = f(buf).min(len);
+                    // SAFETY: `written <= len`, so this is at most one past the end
+                    self.buf.set(Some((unsafe { ptr.add(written) }, len - written)));
+                    break written;
+                }
+                lent => {
+                    self.buf.set(lent);
+                    pending!()
+                }
+            }
+        };
+        yield_now().await;
+        written
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.get().map_or(0, |(_, len)| len)
+    }
+}
+
+impl<T> Default for SliceSlot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drives a body which produces samples on demand, e.g. audio or a waveform for a DAC
+///
+/// Each call to [`Generator::fill`] resumes the body once per block until the buffer is full, so
+/// the body keeps its phase, envelope and so on in ordinary locals between calls:
+///
+/// ```
+/// use async_state_machine_example::{Generator, SliceSlot};
+/// use core::pin::pin;
+///
+/// /// A square wave with a period of 4 samples, produced 3 samples at a time
+/// async fn square(slot: &SliceSlot<i16>) {
+///     let mut phase = 0;
+///     loop {
+///         slot.write(|buf| {
+///             let block = buf.len().min(3);
+///             for sample in &mut buf[..block] {
+///                 *sample = if phase < 2 { 1000 } else { -1000 };
+///                 phase = (phase + 1) % 4;
+///             }
+///             block
+///         })
+///         .await;
+///     }
+/// }
+///
+/// let slot = SliceSlot::new();
+/// let body = pin!(square(&slot));
+/// let mut generator = Generator::new(body, &slot);
+/// let mut buf = [0i16; 5];
+/// assert_eq!(generator.fill(&mut buf), 5);
+/// assert_eq!(buf, [1000, 1000, -1000, -1000, 1000]);
+/// assert_eq!(generator.fill(&mut buf[..3]), 3);
+/// assert_eq!(buf[..3], [1000, -1000, -1000]);
+/// ```
+pub struct Generator<'a, 's, F, T>
+where
+    F: Future<Output = ()>
+{
+    fut: Pin<&'a mut F>,
+    slot: &'s SliceSlot<T>,
+    finished: bool,
+}
+
+impl<'a, 's, F, T> Generator<'a, 's, F, T>
+where
+    F: Future<Output = ()>
+{
+    /// Create a generator from a pinned body, and the slot the body writes through
+    pub fn new(fut: Pin<&'a mut F>, slot: &'s SliceSlot<T>) -> Self {
+        Self { fut, slot, finished: false }
+    }
+
+    /// Fill `out` from the body, returning the number of samples written
+    ///
+    /// This returns early, with `out` partly filled, if the body returns or if it is waiting on
+    /// something other than buffer space. Once the body has returned, this always returns zero.
+    pub fn fill(&mut self, out: &mut [T]) -> usize {
+        if self.finished {
+            return 0;
+        }
+        let len = out.len();
+        let slot = self.slot;
+        slot.buf.set(Some((NonNull::from(out).cast(), len)));
+        // Never leave a dangling pointer in the slot, even if the body panics
+        let _lend = OnExit::new(|| slot.buf.set(None));
+        while slot.remaining() > 0 {
+            let before = slot.remaining();
+            if poll_once(self.fut.as_mut()).is_some() {
+                self.finished = true;
+                break;
+            }
+            if slot.remaining() == before {
+                break;
+            }
+        }
+        len - slot.remaining()
+    }
+
+    /// Whether the body has returned
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wait_until;
+    use core::pin::pin;
+
+    #[test]
+    fn test_fill_stops_when_body_waits_or_returns() {
+        let slot = SliceSlot::new();
+        let ready = Cell::new(false);
+        let body = pin!(async {
+            slot.write(|buf| {
+                buf[0] = 1;
+                1
+            })
+            .await;
+            // Wait for something other than buffer space
+            wait_until(|| ready.get()).await;
+            slot.write(|buf| {
+                buf[..2].copy_from_slice(&[2, 3]);
+                2
+            })
+            .await;
+        });
+        let mut generator = Generator::new(body, &slot);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(generator.fill(&mut buf), 1);
+        assert_eq!(generator.fill(&mut buf[1..]), 0);
+        ready.set(true);
+        assert_eq!(generator.fill(&mut buf[1..]), 2);
+        assert!(generator.is_finished());
+        assert_eq!(generator.fill(&mut buf), 0);
+        assert_eq!(buf, [1, 2, 3, 0]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Scope guards for running exit actions when an async state is left
+
+/// Runs a closure when dropped
+///
+/// This is usually created with the [`on_exit!`](crate::on_exit) macro. Because the guard is a
+/// local variable of the async body, it is stored in the state machine alongside any other locals
+/// which are live across an await point, and it is dropped whenever the enclosing scope is left:
+///
+/// - when the scope runs to completion,
+/// - on an early `return` or `?` out of the scope,
+/// - when the future is dropped while suspended inside the scope (i.e. the machine is cancelled).
+///
+/// The last case is the one which is easy to forget: dropping an [`AsyncStateMachine`]'s future
+/// (e.g. dropping the `Box` it was pinned in) runs the destructors of all locals held at the
+/// current await point, so the exit action runs from *within that drop*. This means the closure:
+///
+/// - cannot await anything, so the cleanup must be synchronous,
+/// - runs in whatever context drops the machine, which may not be the context that polls it,
+/// - should not panic, because it may already be running during an unwind.
+///
+/// Exit actions are not run if the future is leaked (e.g. with `core::mem::forget`), and multiple
+/// guards in one scope run in reverse order of declaration.
+///
+/// [`AsyncStateMachine`]: crate::AsyncStateMachine
+pub struct OnExit<F: FnOnce()> {
+    action: Option<F>,
+}
+
+impl<F: FnOnce()> OnExit<F> {
+    /// Create a guard which calls `action` when it is dropped
+    pub fn new(action: F) -> Self {
+        Self { action: Some(action) }
+    }
+
+    /// Disarm the guard, so that the action is never run
+    pub fn disarm(mut self) {
+        self.action = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for OnExit<F> {
+    fn drop(&mut self) {
+        if let Some(action) = self.action.take() {
+            action();
+        }
+    }
+}
+
+/// Run an action when the current scope is left for any reason
+///
+/// Expands to a hidden [`OnExit`](crate::OnExit) guard bound to the enclosing block, so the
+/// closure runs when the block completes, returns early, or when the future containing it is
+/// dropped while suspended inside the block.
+///
+/// ```
+/// use async_state_machine_example::on_exit;
+/// use std::cell::Cell;
+///
+/// let running = Cell::new(false);
+/// let fsm = async {
+///     running.set(true);
+///     on_exit!(|| running.set(false));
+///     futures::pending!();
+/// };
+/// // Dropping the machine before it completes still runs the exit action
+/// let mut fsm = Box::pin(fsm);
+/// async_state_machine_example::poll_once(fsm.as_mut());
+/// assert!(running.get());
+/// drop(fsm);
+/// assert!(!running.get());
+/// ```
+///
+/// See [`OnExit`](crate::OnExit) for details on how this interacts with cancellation.
+#[macro_export]
+macro_rules! on_exit {
+    ($action:expr) => {
+        let _on_exit_guard = $crate::OnExit::new($action);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::poll_once;
+    use core::cell::Cell;
+    use core::pin::pin;
+    use futures::pending;
+
+    #[test]
+    fn test_on_exit_runs_on_completion() {
+        let exited = Cell::new(false);
+        let mut fsm = pin!(async {
+            on_exit!(|| exited.set(true));
+            pending!();
+        });
+
+        assert_eq!(poll_once(fsm.as_mut()), None);
+        assert!(!exited.get());
+        assert_eq!(poll_once(fsm.as_mut()), Some(()));
+        assert!(exited.get());
+    }
+
+    #[test]
+    fn test_on_exit_runs_on_cancel() {
+        let exited = Cell::new(0);
+        let mut fsm = Box::pin(async {
+            on_exit!(|| exited.set(exited.get() + 1));
+            loop {
+                pending!();
+            }
+        });
+
+        assert_eq!(poll_once(fsm.as_mut()), None);
+        assert_eq!(exited.get(), 0);
+        drop(fsm);
+        assert_eq!(exited.get(), 1);
+    }
+}
@@ -0,0 +1,244 @@
+//! What to do when every machine is waiting
+
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+/// A way of waiting, while no machine has any work to do
+///
+/// The strategy supplies the waker machines are polled with, and [`IdleStrategy::idle`] must
+/// return promptly once that waker is woken, including by a wake which arrived just before it was
+/// called. Strategies which sleep the core until an interrupt rely on the interrupt's handler to
+/// wake the waker (or to return from the sleep by itself, as on Cortex-M).
+///
+/// Choosing the strategy when constructing the loop or executor lets the same machines run on
+/// any target: [`Spin`] anywhere, [`Sleep`] or [`Park`] with std, `WfeSev` on Cortex-M with the
+/// `cortex-m` feature, and `Wfi` on RISC-V with the `riscv` feature. Any `FnMut(Duration)` is
+/// also a strategy, which is called with the time to wait and whose waker does nothing.
+pub trait IdleStrategy {
+    /// The waker to poll machines with
+    fn waker(&self) -> Waker {
+        Waker::noop().clone()
+    }
+
+    /// Wait until woken, for at most `timeout`
+    ///
+    /// Returning early is always allowed: callers check whether they have work, and idle again
+    /// if not.
+    fn idle(&mut self, timeout: Duration);
+}
+
+impl<F: FnMut(Duration)> IdleStrategy for F {
+    fn idle(&mut self, timeout: Duration) {
+        self(timeout)
+    }
+}
+
+/// Returns straight away, so callers poll continuously
+///
+/// This never misses a wake, including from code which doesn't wake at all, at the cost of
+/// keeping the core busy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Spin;
+
+impl IdleStrategy for Spin {
+    fn idle(&mut self, _timeout: Duration) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Sleeps the thread for the whole timeout, ignoring wakes
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sleep;
+
+#[cfg(feature = "std")]
+impl IdleStrategy for Sleep {
+    fn idle(&mut self, timeout: Duration) {
+        std::thread::sleep(timeout);
+    }
+}
+
+/// Parks the thread until woken, or until the timeout
+///
+/// The waker unparks the thread which created the strategy, so it can be woken from other
+/// threads. An unpark which arrives before the thread parks makes the park return immediately.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct Park {
+    thread: std::thread::Thread,
+}
+
+#[cfg(feature = "std")]
+impl Park {
+    /// Create a strategy which parks the current thread
+    pub fn new() -> Self {
+        Self { thread: std::thread::current() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Park {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+struct Unpark(std::thread::Thread);
+
+#[cfg(feature = "std")]
+impl std::task::Wake for Unpark {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(feature = "std")]
+impl IdleStrategy for Park {
+    fn waker(&self) -> Waker {
+        std::sync::Arc::new(Unpark(self.thread.clone())).into()
+    }
+
+    fn idle(&mut self, timeout: Duration) {
+        if timeout == Duration::MAX {
+            std::thread::park();
+        } else {
+            std::thread::park_timeout(timeout);
+        }
+    }
+}
+
+/// Idles on the Cortex-M `WFE` instruction, with wakes executing `SEV`
+///
+/// `SEV` sets the core's event register, which makes the next `WFE` return immediately, so a wake
+/// which arrives while machines are being polled is never lost. Interrupts also end a `WFE`, so
+/// the timeout is ignored: use a timer interrupt such as SysTick to bound the wait. The waker
+/// needs no allocation and can be woken from interrupt handlers. See the `cortex_m_idle` example
+/// for its use with an interrupt-driven [`EdgeSignal`](crate::EdgeSignal).
+#[cfg(feature = "cortex-m")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WfeSev;
+
+#[cfg(feature = "cortex-m")]
+impl IdleStrategy for WfeSev {
+    fn waker(&self) -> Waker {
+        use core::task::{RawWaker, RawWakerVTable};
+
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn wake(_: *const ()) {
+            cortex_m::asm::sev();
+        }
+        fn drop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+        // SAFETY: The vtable functions ignore the data pointer, and are safe to call from any
+        // context
+        unsafe { Waker::from_raw(clone(core::ptr::null())) }
+    }
+
+    fn idle(&mut self, _timeout: Duration) {
+        cortex_m::asm::wfe();
+    }
+}
+
+#[cfg(feature = "riscv")]
+static WFI_WOKEN: crate::AtomicFlag = crate::AtomicFlag::new(false);
+
+/// Idles on the RISC-V `WFI` instruction, in machine mode
+///
+/// `WFI` has no event register to record a wake, so the waker sets a flag instead, and `idle`
+/// only executes `WFI` if the flag is clear. Both happen with interrupts masked, which `WFI`
+/// still returns from if one is pending, so a wake from an interrupt handler is never lost.
+/// Interrupts end the wait, so the timeout is ignored: use a timer interrupt to bound it. Usage
+/// is the same as `WfeSev`'s, see the `cortex_m_idle` example.
+#[cfg(feature = "riscv")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wfi;
+
+#[cfg(feature = "riscv")]
+impl IdleStrategy for Wfi {
+    fn waker(&self) -> Waker {
+        use core::task::{RawWaker, RawWakerVTable};
+
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn wake(_: *const ()) {
+            WFI_WOKEN.set();
+        }
+        fn drop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+        // SAFETY: The vtable functions ignore the data pointer, and are safe to call from any
+        // context
+        unsafe { Waker::from_raw(clone(core::ptr::null())) }
+    }
+
+    fn idle(&mut self, _timeout: Duration) {
+        riscv::interrupt::free(|| {
+            if !WFI_WOKEN.take() {
+                riscv::asm::wfi();
+            }
+        });
+    }
+}
+
+/// Run a machine to completion, idling with `idle` whenever it is waiting
+///
+/// The machine is polled with the strategy's waker, and polled again each time `idle` returns.
+pub fn block_on<F: Future>(mut fut: Pin<&mut F>, idle: &mut impl IdleStrategy) -> F::Output {
+    let waker = idle.waker();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+        idle.idle(Duration::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{yield_times, AtomicFlag, Mailbox};
+    use core::pin::pin;
+
+    #[test]
+    fn test_block_on_idles_between_polls() {
+        let mailbox = Mailbox::new();
+        let mut idles = 0;
+        let fut = pin!(async {
+            yield_times(2).await;
+            mailbox.recv().await
+        });
+        let result = block_on(fut, &mut |_| {
+            idles += 1;
+            if idles == 3 {
+                mailbox.post(7).unwrap();
+            }
+        });
+        assert_eq!((result, idles), (7, 3));
+    }
+
+    #[test]
+    fn test_park_is_unparked_by_waker() {
+        let flag = AtomicFlag::new(false);
+        // Waits without waking, so only the other thread's wake ends the park
+        let fut = pin!(core::future::poll_fn(|_| match flag.get() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }));
+        let mut idle = Park::new();
+        let waker = idle.waker();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                flag.set();
+                waker.wake_by_ref();
+            });
+            block_on(fut, &mut idle);
+        });
+    }
+}
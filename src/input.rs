@@ -0,0 +1,127 @@
+//! Passing one value into a machine with each poll
+
+use core::cell::Cell;
+use core::pin::Pin;
+
+use futures::pending;
+
+use crate::AsyncStateMachine;
+
+/// A slot through which the caller passes a value into the body for the duration of one poll
+pub struct Input<I> {
+    slot: Cell<Option<I>>,
+}
+
+impl<I> Input<I> {
+    pub const fn new() -> Self {
+        Self { slot: Cell::new(None) }
+    }
+
+    /// Wait for a poll which carries an input value, and take it
+    ///
+    /// If the current poll carries a value which hasn't been taken yet, this completes
+    /// immediately. Otherwise it yields without waking, so the machine reports
+    /// [`Step::Stalled`](crate::Step::Stalled): there is no point polling it again without input.
+    pub async fn take_input(&self) -> I {
+        loop {
+            if let Some(value) = self.slot.take() {
+                return value;
+            }
+            pending!()
+        }
+    }
+}
+
+impl<I> Default for Input<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A state machine which accepts a value of type `I` with each poll
+///
+/// This suits parsers and protocol handlers which consume one unit of input per step, written
+/// linearly with [`Input::take_input`]:
+///
+/// ```
+/// use async_state_machine_example::{Input, InputStateMachine};
+/// use core::pin::pin;
+///
+/// async fn sum_until_zero(input: &Input<u32>) -> u32 {
+///     let mut sum = 0;
+///     loop {
+///         match input.take_input().await {
+///             0 => return sum,
+///             n => sum += n,
+///         }
+///     }
+/// }
+///
+/// let input = Input::new();
+/// let fut = pin!(sum_until_zero(&input));
+/// let mut fsm = InputStateMachine::new(fut, &input);
+/// assert_eq!(fsm.exec_with_input(3), None);
+/// assert_eq!(fsm.exec_with_input(4), None);
+/// assert_eq!(fsm.exec_with_input(0), Some(7));
+/// ```
+pub struct InputStateMachine<'a, 'i, F, I>
+where
+    F: Future
+{
+    machine: AsyncStateMachine<'a, F, F::Output>,
+    input: &'i Input<I>,
+}
+
+impl<'a, 'i, F, I> InputStateMachine<'a, 'i, F, I>
+where
+    F: Future
+{
+    /// Create a machine from a pinned future, and the input slot the future reads from
+    pub fn new(fut: Pin<&'a mut F>, input: &'i Input<I>) -> Self {
+        Self { machine: AsyncStateMachine::new(fut), input }
+    }
+
+    /// Poll the future once, with `value` available to [`Input::take_input`] during the poll
+    ///
+    /// The value is only available for this poll: if the body doesn't take it, it is dropped
+    /// afterwards.
+    pub fn exec_with_input(&mut self, value: I) -> Option<F::Output> {
+        self.input.slot.set(Some(value));
+        let result = self.machine.exec();
+        self.input.slot.set(None);
+        result
+    }
+
+    /// Poll the future once without any input
+    pub fn exec(&mut self) -> Option<F::Output> {
+        self.machine.exec()
+    }
+
+    /// The underlying machine, e.g. for its wake statistics
+    pub fn machine(&self) -> &AsyncStateMachine<'a, F, F::Output> {
+        &self.machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::pin::pin;
+
+    #[test]
+    fn test_input_only_lasts_one_poll() {
+        let input = Input::new();
+        let fut = pin!(async {
+            crate::yield_now().await;
+            input.take_input().await
+        });
+        let mut fsm = InputStateMachine::new(fut, &input);
+
+        // Input offered while the body is busy elsewhere is dropped
+        assert_eq!(fsm.exec_with_input(1), None);
+        assert_eq!(fsm.exec(), None);
+        // Waiting for input doesn't ask to be polled again
+        assert!(!fsm.machine().was_woken_since_last_poll());
+        assert_eq!(fsm.exec_with_input(2), Some(2));
+    }
+}
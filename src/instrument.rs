@@ -0,0 +1,85 @@
+//! Poll accounting for each await point of a machine, filled in by `#[instrument_machine]`
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+/// The number of polls spent at each await point of an instrumented machine
+///
+/// Declared by [`instrument_machine`](crate::instrument_machine), with one entry per `.await` in
+/// the function, labelled with the awaited expression. Counts accumulate over every run of the
+/// machine until [`AwaitStats::reset`] is called.
+///
+/// ```
+/// use async_state_machine_example::{instrument_machine, poll_once, yield_times};
+/// use core::pin::pin;
+///
+/// #[instrument_machine(STATS)]
+/// async fn machine() {
+///     yield_times(3).await;
+///     yield_times(1).await;
+/// }
+///
+/// let mut fut = pin!(machine());
+/// while poll_once(fut.as_mut()).is_none() {}
+/// let stats: Vec<_> = STATS.iter().collect();
+/// assert_eq!(stats, [("yield_times(3)", 4), ("yield_times(1)", 2)]);
+/// ```
+pub struct AwaitStats<const N: usize> {
+    labels: [&'static str; N],
+    polls: [AtomicUsize; N],
+}
+
+impl<const N: usize> AwaitStats<N> {
+    pub const fn new(labels: [&'static str; N]) -> Self {
+        Self { labels, polls: [const { AtomicUsize::new(0) }; N] }
+    }
+
+    /// Each await point's label and poll count, in source order
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        let polls = self.polls.iter().map(|polls| polls.load(Ordering::Relaxed));
+        self.labels.iter().copied().zip(polls)
+    }
+
+    pub fn reset(&self) {
+        for polls in &self.polls {
+            polls.store(0, Ordering::Relaxed);
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn counter(&self, index: usize) -> &AtomicUsize {
+        &self.polls[index]
+    }
+}
+
+/// Wraps an awaited future, counting its polls
+#[doc(hidden)]
+pub struct Instrumented<'a, F> {
+    fut: F,
+    polls: &'a AtomicUsize,
+}
+
+#[doc(hidden)]
+pub fn instrument<F: IntoFuture>(fut: F, polls: &AtomicUsize) -> Instrumented<'_, F::IntoFuture> {
+    Instrumented { fut: fut.into_future(), polls }
+}
+
+impl<F: Future> Future for Instrumented<'_, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: `fut` is structurally pinned, and never moved out
+        let this = unsafe { self.get_unchecked_mut() };
+        // The counter is a static shared by every instance of the machine, which may be polled
+        // from different threads
+        #[cfg(target_has_atomic = "ptr")]
+        this.polls.fetch_add(1, Ordering::Relaxed);
+        // Targets without atomic read-modify-write are single-core, and a count can only be lost
+        // if an interrupt handler polls another instance between the load and the store
+        #[cfg(not(target_has_atomic = "ptr"))]
+        this.polls.store(this.polls.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        // SAFETY: `this` came from a pinned reference, and `fut` is never moved out of it
+        unsafe { Pin::new_unchecked(&mut this.fut) }.poll(cx)
+    }
+}
@@ -0,0 +1,160 @@
+//! Awaiting another machine's completion
+
+use core::cell::Cell;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+enum State<T> {
+    Running,
+    Finished(T),
+    Taken,
+}
+
+/// A one-shot slot which receives a machine's output when it completes
+///
+/// [`Completion::watch`] wraps a machine so that its output is stored here, and any number of
+/// [`JoinHandle`]s, from [`Completion::handle`], can then check on it or await it. This lets the
+/// code which started a machine, or a sibling machine in the same executor, wait for it to
+/// finish, without the executor needing to know about outputs at all.
+///
+/// ```
+/// use async_state_machine_example::{scope, yield_times, AsyncStateMachine, Completion};
+/// use core::pin::pin;
+///
+/// let measured = Completion::new();
+/// let fut = pin!(scope(async |s| {
+///     let measurement = s.spawn_joinable(&measured, async {
+///         yield_times(3).await;
+///         42
+///     });
+///     // A sibling which waits for the measurement to finish
+///     s.spawn(async move {
+///         assert_eq!(measurement.await, 42);
+///     });
+///     measurement.is_finished()
+/// }));
+/// let mut fsm = AsyncStateMachine::new(fut);
+/// let result = loop {
+///     if let Some(result) = fsm.exec() {
+///         break result;
+///     }
+/// };
+/// assert!(!result);
+/// ```
+pub struct Completion<T> {
+    state: Cell<State<T>>,
+}
+
+impl<T> Completion<T> {
+    pub const fn new() -> Self {
+        Self { state: Cell::new(State::Running) }
+    }
+
+    /// Wrap `machine` so that its output is stored in this slot when it completes
+    ///
+    /// The wrapped machine completes with `()`, so it can be run by executors which expect that,
+    /// such as [`Scope::spawn`](crate::Scope::spawn). A slot should only watch one machine.
+    pub async fn watch(&self, machine: impl Future<Output = T>) {
+        let output = machine.await;
+        self.state.set(State::Finished(output));
+    }
+
+    /// A handle for checking on or awaiting the watched machine
+    pub fn handle(&self) -> JoinHandle<'_, T> {
+        JoinHandle { completion: self }
+    }
+
+    fn is_finished(&self) -> bool {
+        let state = self.state.replace(State::Running);
+        let finished = !matches!(state, State::Running);
+        self.state.set(state);
+        finished
+    }
+
+    fn try_take(&self) -> Option<T> {
+        match self.state.replace(State::Taken) {
+            State::Finished(output) => Some(output),
+            state => {
+                self.state.set(state);
+                None
+            }
+        }
+    }
+}
+
+impl<T> Default for Completion<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A handle to a machine watched by a [`Completion`]
+///
+/// Awaiting the handle waits for the machine to complete, and takes its output. Handles are
+/// `Copy`, so several machines can watch the same one, but only the first to take the output
+/// gets it: awaiting a handle whose output was already taken never completes.
+pub struct JoinHandle<'c, T> {
+    completion: &'c Completion<T>,
+}
+
+impl<T> Clone for JoinHandle<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for JoinHandle<'_, T> {}
+
+impl<T> JoinHandle<'_, T> {
+    /// Whether the machine has completed, whether or not its output has been taken
+    pub fn is_finished(&self) -> bool {
+        self.completion.is_finished()
+    }
+
+    /// Take the machine's output, if it has completed and the output hasn't been taken yet
+    pub fn try_take(&self) -> Option<T> {
+        self.completion.try_take()
+    }
+}
+
+impl<T> Future for JoinHandle<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match self.try_take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                // Ask to be polled again, like the other polled waits in this crate
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{poll_once, yield_now};
+    use core::pin::pin;
+
+    #[test]
+    fn test_join_handle() {
+        let completion = Completion::new();
+        let handle = completion.handle();
+        let mut machine = pin!(completion.watch(async {
+            yield_now().await;
+            "done"
+        }));
+        let mut joined = pin!(handle);
+
+        assert_eq!(poll_once(machine.as_mut()), None);
+        assert_eq!(poll_once(joined.as_mut()), None);
+        assert!(!handle.is_finished());
+        assert_eq!(poll_once(machine.as_mut()), Some(()));
+        assert!(handle.is_finished());
+        assert_eq!(poll_once(joined.as_mut()), Some("done"));
+        assert!(handle.is_finished());
+        assert_eq!(handle.try_take(), None);
+    }
+}
@@ -1,4 +1,128 @@
+//! Async functions as state machines, for embedded and hosted systems
+//!
+//! The crate is `no_std`. The default `std` feature adds the items which need an operating
+//! system, such as [`StdClock`] and the thread-based idle strategies, and the `alloc` feature,
+//! which `std` enables, adds the boxed machines and the other items which allocate. The
+//! `cortex-m` and `riscv` features add idle strategies for those cores.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 use core::pin::Pin;
+use core::time::Duration;
+
+mod aead;
+mod armor;
+mod array_executor;
+mod ask;
+mod atomic;
+#[cfg(feature = "alloc")]
+mod boxed;
+mod cancel;
+mod cell;
+mod cobs;
+mod compress;
+mod coroutine;
+mod crc;
+mod cs;
+mod debounce;
+mod edge;
+mod error;
+mod flow;
+mod generator;
+mod guard;
+mod idle;
+mod input;
+mod instrument;
+mod join;
+mod line;
+mod mailbox;
+mod main_loop;
+mod mux;
+mod pattern;
+mod periodic;
+mod persist;
+mod ping_pong;
+mod progress;
+mod queue;
+mod rate_limit;
+#[cfg(feature = "alloc")]
+mod scope;
+mod screen;
+mod serialize;
+mod stage;
+mod ticker;
+mod time;
+mod try_machine;
+mod wait;
+mod waker;
+
+pub use aead::{AuthFailed, Cipher, Decrypt, Encrypt};
+pub use armor::{Base64Decode, Base64Encode, HexDecode, HexEncode, InvalidEncoding};
+pub use ask::{AskStateMachine, Asker};
+pub use async_state_machine_example_derive::{instrument_machine, AsyncSerialize};
+pub use array_executor::{AbortHandle, ArrayExecutor, BudgetOverrun, MachineInfo, SlotStatus};
+pub use atomic::{AtomicFlag, AtomicOption};
+#[cfg(feature = "alloc")]
+pub use boxed::{LocalBoxStateMachine, SendBoxStateMachine};
+pub use cancel::CancelToken;
+pub use cell::{LocalFlag, LocalOption};
+pub use cobs::Cobs;
+pub use compress::{Compress, CorruptInput, Decompress};
+pub use coroutine::{Coroutine, CoroutineState, Yielder};
+pub use crc::Crc;
+pub use cs::CsMailbox;
+pub use debounce::{debounce, watch_debounced, Throttle};
+pub use edge::{Edge, EdgeSignal};
+pub use error::Error;
+pub use flow::{Credits, LocalCredits};
+pub use generator::{Generator, SliceSlot};
+pub use guard::OnExit;
+#[cfg(feature = "cortex-m")]
+pub use idle::WfeSev;
+#[cfg(feature = "riscv")]
+pub use idle::Wfi;
+pub use idle::{block_on, IdleStrategy, Spin};
+#[cfg(feature = "std")]
+pub use idle::{Park, Sleep};
+pub use input::{Input, InputStateMachine};
+#[doc(hidden)]
+pub use instrument::instrument;
+pub use instrument::AwaitStats;
+pub use join::{Completion, JoinHandle};
+pub use line::{expect, read_line, LineBuffer, LineTooLong};
+pub use mailbox::Mailbox;
+pub use main_loop::{LoopStats, MainLoop};
+pub use mux::{demux, mux, DemuxError, DemuxStream, FrameTooLong, MuxStream};
+pub use pattern::{PatternPlayer, MAX_STEPS_PER_EXEC};
+pub use periodic::Overrun;
+#[cfg(feature = "alloc")]
+pub use periodic::Periodic;
+pub use persist::{InvalidState, PersistentStateMachine};
+pub use ping_pong::PingPong;
+pub use progress::{Progress, ProgressReport};
+pub use queue::EventQueue;
+pub use rate_limit::RateLimited;
+#[cfg(feature = "alloc")]
+pub use scope::{scope, Scope};
+pub use screen::{edit_value, menu, show, Button, Screen};
+#[cfg(feature = "alloc")]
+pub use serialize::read_frame;
+pub use serialize::{write_frame, write_text, AsyncSerialize, ByteSink, TextBuffer, YieldingSink};
+pub use stage::{Chain, Stage, Staged};
+#[cfg(feature = "alloc")]
+pub use stage::{Pipeline, PipelineReader};
+pub use ticker::{interval, Ticker};
+pub use time::{timeout, Clock, MockClock, TimedOut};
+#[cfg(feature = "std")]
+pub use time::StdClock;
+pub use try_machine::TryStateMachine;
+pub use wait::{busy_wait, delay_polls, retry, wait_until, yield_now, yield_times};
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+pub use waker::CountingWaker;
+use waker::MachineWaker;
 
 /// A wrapper struct to execute a future one call at a time
 pub struct AsyncStateMachine<'a, F, T>
@@ -6,8 +130,130 @@ where
     F: Future<Output = T>
 {
     fut: Pin<&'a mut F>,
+    stepper: Stepper,
+}
+
+/// The completion and wake tracking shared by the machines which own or borrow a future
+struct Stepper {
+    finished: bool,
+    waker: MachineWaker,
+    wakes_before_poll: usize,
+}
+
+impl Stepper {
+    fn new() -> Self {
+        Self { finished: false, waker: MachineWaker::new(), wakes_before_poll: 0 }
+    }
+
+    fn try_exec<F>(&mut self, fut: Pin<&mut F>) -> Result<Step<F::Output>, AlreadyFinished>
+    where
+        F: Future + ?Sized
+    {
+        if self.finished {
+            return Err(AlreadyFinished);
+        }
+        self.wakes_before_poll = self.waker.wake_count();
+        match self.waker.poll(fut) {
+            core::task::Poll::Ready(result) => {
+                self.finished = true;
+                Ok(Step::Ready(result))
+            }
+            core::task::Poll::Pending if self.was_woken_since_last_poll() => Ok(Step::Pending),
+            core::task::Poll::Pending => Ok(Step::Stalled),
+        }
+    }
+
+    fn was_woken_since_last_poll(&self) -> bool {
+        self.waker.wake_count() != self.wakes_before_poll
+    }
+}
+
+/// The outcome of polling a state machine once
+///
+/// `Q` is the type of request a machine can make of its caller, see
+/// [`AskStateMachine`]. For machines which can't make requests it is uninhabited, and
+/// [`Step::Request`] never occurs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step<T, Q = core::convert::Infallible> {
+    /// The future is still pending, and has asked to be polled again
+    Pending,
+    /// The future is pending without having woken its waker during the poll, or it has already
+    /// completed
+    ///
+    /// Polling again is pointless unless something external changes, such as a wake from an
+    /// interrupt or another machine. A machine which is stalled and never woken is likely
+    /// deadlocked. Note that a future yielding with `futures::pending!()` does not wake, and so
+    /// always reports as stalled; the primitives in this crate wake when they yield.
+    Stalled,
+    /// The future was not polled, e.g. because a [`RateLimited`] wrapper held it off
+    Skipped,
+    /// The future is suspended until the caller answers this request
+    Request(Q),
+    /// The future completed with this value
+    Ready(T),
+}
+
+impl<T, Q> Step<T, Q> {
+    /// Convert to an `Option`, with `Some` for a ready value
+    pub fn ready(self) -> Option<T> {
+        match self {
+            Step::Ready(value) => Some(value),
+            Step::Pending | Step::Stalled | Step::Skipped | Step::Request(_) => None,
+        }
+    }
 }
 
+/// A state machine which can be stepped one poll at a time
+///
+/// This allows wrappers such as [`RateLimited`] to be written once for any machine.
+pub trait Machine {
+    type Output;
+
+    /// Poll the machine one time, see [`AsyncStateMachine::exec_step`]
+    fn exec_step(&mut self) -> Step<Self::Output>;
+
+    /// Poll the machine one time, returning its output if it completed
+    fn exec(&mut self) -> Option<Self::Output> {
+        self.exec_step().ready()
+    }
+}
+
+impl<F, T> Machine for AsyncStateMachine<'_, F, T>
+where
+    F: Future<Output = T>
+{
+    type Output = T;
+
+    fn exec_step(&mut self) -> Step<T> {
+        AsyncStateMachine::exec_step(self)
+    }
+
+    fn exec(&mut self) -> Option<T> {
+        AsyncStateMachine::exec(self)
+    }
+}
+
+/// Statistics for a run of polls, passed to the predicate of [`AsyncStateMachine::exec_while`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PollStats {
+    /// The number of polls performed so far in this run
+    pub polls: usize,
+    /// The time elapsed since the start of this run
+    pub elapsed: Duration,
+}
+
+/// Error returned when stepping a state machine which has already completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlreadyFinished;
+
+impl core::fmt::Display for AlreadyFinished {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("state machine has already finished")
+    }
+}
+
+impl core::error::Error for AlreadyFinished {}
+
 impl<'a, F, T> AsyncStateMachine<'a, F, T>
 where
     F: Future<Output = T>
@@ -17,15 +263,118 @@ where
     /// `fut` must be pinned. This can be achieved using either `Box::pin` to pin on the heap, or
     /// the `pin!` macro to pin on the stack.
     pub fn new(fut: Pin<&'a mut F>) -> Self {
-        Self { fut: fut }
+        Self { fut, stepper: Stepper::new() }
+    }
+
+    /// Create a new state machine from a future which is `Unpin`, without pinning it first
+    pub fn new_unpin(fut: &'a mut F) -> Self
+    where
+        F: Unpin
+    {
+        Self::new(Pin::new(fut))
     }
 
     /// Poll the future one time
     ///
     /// If the future completes, Some(T) is returned with the returned value. If the future is still
-    /// pending, then None is returned
+    /// pending, then None is returned. Once the future has completed it is not polled again, and
+    /// every later call returns None; use [`AsyncStateMachine::try_exec`] to treat that as an
+    /// error instead.
+    ///
+    /// A machine can't be polled from inside its own body: `exec` takes `&mut self`, and the body
+    /// can't hold a reference to the machine it is running in. If the machine is shared through a
+    /// `RefCell`, an attempt to re-enter fails with the `RefCell`'s "already borrowed" panic.
     pub fn exec(&mut self) -> Option<T> {
-        poll_once(self.fut.as_mut())
+        self.try_exec().ok()?.ready()
+    }
+
+    /// Poll the future one time, failing if it has already completed
+    pub fn try_exec(&mut self) -> Result<Step<T>, AlreadyFinished> {
+        self.stepper.try_exec(self.fut.as_mut())
+    }
+
+    /// Poll the future one time, reporting whether it is worth polling again
+    ///
+    /// This is [`AsyncStateMachine::try_exec`], with a finished machine treated as
+    /// [`Step::Stalled`] rather than an error.
+    pub fn exec_step(&mut self) -> Step<T> {
+        self.try_exec().unwrap_or(Step::Stalled)
+    }
+
+    /// Poll the future repeatedly for as long as `predicate` returns true
+    ///
+    /// The predicate is checked before every poll, including the first, with the statistics for
+    /// the run so far. This can express any polling budget, e.g. a poll count, a time budget from
+    /// `clock`, or a [`Progress`] threshold captured by the closure:
+    ///
+    /// ```ignore
+    /// // Use up to 80% of a 1ms frame
+    /// fsm.exec_while(&clock, |stats| stats.elapsed < Duration::from_micros(800));
+    /// ```
+    ///
+    /// Returns the result if the future completes during the run.
+    pub fn exec_while(
+        &mut self,
+        clock: &impl Clock,
+        mut predicate: impl FnMut(&PollStats) -> bool,
+    ) -> Option<T> {
+        let start = clock.now();
+        let mut stats = PollStats::default();
+        while !self.stepper.finished && predicate(&stats) {
+            if let Some(result) = self.exec() {
+                return Some(result);
+            }
+            stats.polls += 1;
+            stats.elapsed = clock.now() - start;
+        }
+        None
+    }
+
+    /// The number of times the future has woken its waker
+    ///
+    /// Without the `alloc` feature, clones of the waker which the future stores, e.g. for an
+    /// interrupt handler to wake, can't keep a count for this machine alone. Their wakes are
+    /// counted in a total shared by every machine, which is added to this machine's own count, so
+    /// the count may include wakes meant for other machines.
+    pub fn wake_count(&self) -> usize {
+        self.stepper.waker.wake_count()
+    }
+
+    /// True if the future has woken its waker since the start of the most recent poll
+    ///
+    /// A future which returns pending without arranging to be woken is relying on being polled
+    /// again regardless, e.g. one using `futures::pending!()` in a loop. See [`Step::Stalled`].
+    pub fn was_woken_since_last_poll(&self) -> bool {
+        self.stepper.was_woken_since_last_poll()
+    }
+
+    /// True once the future has completed
+    pub fn is_finished(&self) -> bool {
+        self.stepper.finished
+    }
+
+    /// Poll the future repeatedly until it completes or `deadline` is reached
+    ///
+    /// The future is always polled at least once, even if the deadline has already passed. The
+    /// deadline is only checked between polls, so a single long poll can overrun it. Returns
+    /// `None` straight away if the future has already completed.
+    #[cfg(feature = "std")]
+    pub fn exec_until(&mut self, deadline: std::time::Instant) -> Option<T> {
+        loop {
+            match self.try_exec() {
+                Ok(Step::Ready(result)) => return Some(result),
+                Err(AlreadyFinished) => return None,
+                Ok(_) => (),
+            }
+            if std::time::Instant::now() >= deadline {
+                return None;
+            }
+        }
+    }
+
+    /// Like [`AsyncStateMachine::exec_until`], with the deadline given as a time on `clock`
+    pub fn exec_until_clock(&mut self, deadline: Duration, clock: &impl Clock) -> Option<T> {
+        self.exec_while(clock, |stats| stats.polls == 0 || clock.now() < deadline)
     }
 }
 
@@ -39,6 +388,13 @@ pub fn poll_once<T>(mut f: Pin<&mut dyn Future<Output = T>>) -> Option<T> {
     }
 }
 
+/// Poll an `Unpin` future one time, and return its result if it completes
+///
+/// This is [`poll_once`] for futures which don't need pinning, such as boxed futures.
+pub fn poll_once_unpin<F: Future + Unpin>(f: &mut F) -> Option<F::Output> {
+    poll_once(Pin::new(f) as Pin<&mut F>)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -66,4 +422,118 @@ mod tests {
         // i = 2. Done!
         assert_eq!(poll_once(future.as_mut()), Some(42));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_try_exec_after_completion() {
+        let future = pin!(async {
+            pending!();
+            1
+        });
+        let mut fsm = AsyncStateMachine::new(future);
+
+        assert_eq!(fsm.try_exec(), Ok(Step::Stalled));
+        assert_eq!(fsm.try_exec(), Ok(Step::Ready(1)));
+        assert!(fsm.is_finished());
+        assert_eq!(fsm.try_exec(), Err(AlreadyFinished));
+        assert_eq!(fsm.exec(), None);
+    }
+
+    #[test]
+    fn test_wake_tracking() {
+        let future = pin!(async {
+            // Yield without asking to be woken
+            pending!();
+            // Wake the waker, then yield
+            futures::future::poll_fn(|cx| {
+                cx.waker().wake_by_ref();
+                core::task::Poll::Ready(())
+            })
+            .await;
+            pending!();
+        });
+        let mut fsm = AsyncStateMachine::new(future);
+
+        assert_eq!(fsm.exec(), None);
+        assert!(!fsm.was_woken_since_last_poll());
+        assert_eq!(fsm.exec(), None);
+        assert!(fsm.was_woken_since_last_poll());
+        assert_eq!(fsm.wake_count(), 1);
+        assert_eq!(fsm.exec(), Some(()));
+        assert!(!fsm.was_woken_since_last_poll());
+    }
+
+    #[test]
+    fn test_exec_step_stalled() {
+        let ready = core::cell::Cell::new(false);
+        let future = pin!(async {
+            wait_until(|| ready.get()).await;
+            // Waits for a wake which never comes
+            futures::future::pending::<()>().await;
+        });
+        let mut fsm = AsyncStateMachine::new(future);
+
+        assert_eq!(fsm.exec_step(), Step::Pending);
+        ready.set(true);
+        assert_eq!(fsm.exec_step(), Step::Stalled);
+        assert_eq!(fsm.exec_step(), Step::Stalled);
+    }
+
+    #[test]
+    fn test_exec_while() {
+        let clock = MockClock::new();
+        let future = pin!(async {
+            for _ in 0..10 {
+                clock.advance(Duration::from_millis(1));
+                pending!()
+            }
+        });
+        let mut fsm = AsyncStateMachine::new(future);
+
+        assert_eq!(fsm.exec_while(&clock, |stats| stats.polls < 3), None);
+        assert_eq!(clock.now(), Duration::from_millis(3));
+        let budget = Duration::from_millis(5);
+        assert_eq!(fsm.exec_while(&clock, |stats| stats.elapsed < budget), None);
+        assert_eq!(clock.now(), Duration::from_millis(8));
+        assert_eq!(fsm.exec_while(&clock, |_| true), Some(()));
+        assert_eq!(fsm.exec_while(&clock, |_| true), None);
+    }
+
+    #[test]
+    fn test_unpin() {
+        let mut future = Box::pin(async {
+            pending!();
+            7
+        });
+        assert_eq!(poll_once_unpin(&mut future), None);
+        assert_eq!(AsyncStateMachine::new_unpin(&mut future).exec(), Some(7));
+    }
+
+    #[test]
+    fn test_exec_until_clock() {
+        let clock = MockClock::new();
+        let ms = Duration::from_millis(1);
+        let future = pin!(async {
+            for _ in 0..10 {
+                clock.advance(ms);
+                pending!()
+            }
+        });
+        let mut fsm = AsyncStateMachine::new(future);
+
+        assert_eq!(fsm.exec_until_clock(4 * ms, &clock), None);
+        assert_eq!(clock.now(), 4 * ms);
+        assert_eq!(fsm.exec_until_clock(20 * ms, &clock), Some(()));
+        assert_eq!(clock.now(), 10 * ms);
+    }
+
+    #[test]
+    fn test_exec_until_returns_once_finished() {
+        let mut future = core::future::ready(1);
+        let mut fsm = AsyncStateMachine::new_unpin(&mut future);
+        let deadline = std::time::Instant::now() + Duration::from_secs(3600);
+
+        assert_eq!(fsm.exec_until(deadline), Some(1));
+        // Would spin for an hour if a finished machine were polled until the deadline
+        assert_eq!(fsm.exec_until(deadline), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! Line-oriented input, e.g. for text protocols such as modem AT commands
+
+use crate::yield_now;
+
+/// Error returned when a line does not fit in a [`LineBuffer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineTooLong;
+
+impl core::fmt::Display for LineTooLong {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("line too long")
+    }
+}
+
+impl core::error::Error for LineTooLong {}
+
+/// Assembles incoming bytes into lines
+///
+/// Lines are terminated by `\n`, and a trailing `\r` is stripped, so both `\n` and `\r\n` line
+/// endings are accepted. A line longer than `N` bytes is discarded up to its terminator and
+/// reported as [`LineTooLong`].
+pub struct LineBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflow: bool,
+    /// A `\r` arrived when the buffer was full, which only fits if it is stripped by a `\n` next
+    pending_cr: bool,
+    complete: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        Self { buf: [0; N], len: 0, overflow: false, pending_cr: false, complete: false }
+    }
+
+    /// Add one byte
+    ///
+    /// Returns `Some` with the line length once a line is complete, at which point the line can be
+    /// read with [`LineBuffer::line`]. It remains available until the next byte is pushed.
+    pub fn push(&mut self, byte: u8) -> Option<Result<usize, LineTooLong>> {
+        if self.complete {
+            self.clear();
+        }
+        if self.pending_cr && byte != b'\n' {
+            self.overflow = true;
+        }
+        self.pending_cr = false;
+        match byte {
+            b'\n' => {
+                self.complete = true;
+                if self.overflow {
+                    self.len = 0;
+                    return Some(Err(LineTooLong));
+                }
+                if self.len > 0 && self.buf[self.len - 1] == b'\r' {
+                    self.len -= 1;
+                }
+                Some(Ok(self.len))
+            }
+            _ if self.len < N => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                None
+            }
+            b'\r' if !self.overflow => {
+                self.pending_cr = true;
+                None
+            }
+            _ => {
+                self.overflow = true;
+                None
+            }
+        }
+    }
+
+    /// The most recently completed line, or the partial line received so far
+    pub fn line(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Discard any partially received line
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.overflow = false;
+        self.pending_cr = false;
+        self.complete = false;
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read the next line from `rx`
+///
+/// `rx` returns the next received byte, or `None` if no byte is currently available, in which
+/// case the future yields until it is polled again. The returned line does not include its
+/// terminator.
+pub async fn read_line<const N: usize>(
+    mut rx: impl FnMut() -> Option<u8>,
+    buf: &mut LineBuffer<N>,
+) -> Result<&[u8], LineTooLong> {
+    loop {
+        match rx() {
+            Some(byte) => {
+                if let Some(result) = buf.push(byte) {
+                    result?;
+                    return Ok(buf.line());
+                }
+            }
+            None => yield_now().await,
+        }
+    }
+}
+
+/// Read lines from `rx` until `matcher` accepts one
+///
+/// `matcher` is called for each received line, and the future completes with the first `Some`
+/// value it returns. Lines for which it returns `None` are skipped, which allows it to collect
+/// intermediate lines (e.g. the information lines preceding an AT command's final `OK`).
+pub async fn expect<const N: usize, R>(
+    mut rx: impl FnMut() -> Option<u8>,
+    buf: &mut LineBuffer<N>,
+    mut matcher: impl FnMut(&[u8]) -> Option<R>,
+) -> Result<R, LineTooLong> {
+    loop {
+        let line = read_line(&mut rx, buf).await?;
+        if let Some(result) = matcher(line) {
+            return Ok(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll_once;
+    use core::pin::pin;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_line_buffer() {
+        let mut buf = LineBuffer::<4>::new();
+        let mut lines = Vec::new();
+        for &b in b"ab\r\ntoolong\nok\n" {
+            if let Some(result) = buf.push(b) {
+                lines.push(result.map(|_| buf.line().to_vec()));
+            }
+        }
+        assert_eq!(lines, [Ok(b"ab".to_vec()), Err(LineTooLong), Ok(b"ok".to_vec())]);
+    }
+
+    #[test]
+    fn test_full_line_with_crlf() {
+        let mut buf = LineBuffer::<4>::new();
+        let mut lines = Vec::new();
+        for &b in b"abcd\r\nabcd\rx\nabcd\r\r\nabcd\n" {
+            if let Some(result) = buf.push(b) {
+                lines.push(result.map(|_| buf.line().to_vec()));
+            }
+        }
+        assert_eq!(lines, [
+            Ok(b"abcd".to_vec()),
+            Err(LineTooLong),
+            Err(LineTooLong),
+            Ok(b"abcd".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_expect_across_polls() {
+        let input = RefCell::new(VecDeque::from(b"+CSQ: 20,0\r\n\r\nO".to_vec()));
+        let mut buf = LineBuffer::<16>::new();
+        let mut info = Vec::new();
+        {
+            let mut fut = pin!(expect(|| input.borrow_mut().pop_front(), &mut buf, |line| {
+                if line == b"OK" {
+                    Some(())
+                } else {
+                    if !line.is_empty() {
+                        info.push(line.to_vec());
+                    }
+                    None
+                }
+            }));
+            assert_eq!(poll_once(fut.as_mut()), None);
+            input.borrow_mut().extend(b"K\r\n");
+            assert_eq!(poll_once(fut.as_mut()), Some(Ok(())));
+        }
+        assert_eq!(info, [b"+CSQ: 20,0".to_vec()]);
+    }
+}
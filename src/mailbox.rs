@@ -0,0 +1,55 @@
+//! Single-slot mailboxes for passing commands into a machine
+
+use core::cell::Cell;
+
+use crate::yield_now;
+
+/// A slot holding at most one message
+///
+/// The sender [`post`](Mailbox::post)s a message between polls, and the machine picks it up with
+/// [`recv`](Mailbox::recv). Posting to a full mailbox fails rather than overwriting, so a command
+/// can't be lost because the machine hasn't got round to reading the previous one.
+pub struct Mailbox<T> {
+    slot: Cell<Option<T>>,
+}
+
+impl<T> Mailbox<T> {
+    pub const fn new() -> Self {
+        Self { slot: Cell::new(None) }
+    }
+
+    /// Store `msg` in the mailbox, or hand it back if the mailbox is already full
+    pub fn post(&self, msg: T) -> Result<(), T> {
+        match self.slot.take() {
+            Some(existing) => {
+                self.slot.set(Some(existing));
+                Err(msg)
+            }
+            None => {
+                self.slot.set(Some(msg));
+                Ok(())
+            }
+        }
+    }
+
+    /// Take the message, if there is one
+    pub fn try_take(&self) -> Option<T> {
+        self.slot.take()
+    }
+
+    /// Wait for a message to be posted, and take it
+    pub async fn recv(&self) -> T {
+        loop {
+            if let Some(msg) = self.slot.take() {
+                return msg;
+            }
+            yield_now().await
+        }
+    }
+}
+
+impl<T> Default for Mailbox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
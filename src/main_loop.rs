@@ -0,0 +1,165 @@
+//! A fixed-rate main loop, with timing statistics
+
+use core::time::Duration;
+
+use crate::periodic::next_deadline;
+use crate::{Clock, IdleStrategy, Overrun};
+
+#[cfg(feature = "std")]
+type DefaultIdle = crate::Sleep;
+#[cfg(not(feature = "std"))]
+type DefaultIdle = crate::Spin;
+
+/// Timing statistics gathered by a [`MainLoop`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoopStats {
+    pub ticks: usize,
+    /// How late the most recent tick started
+    pub jitter: Duration,
+    /// The latest any tick has started
+    pub max_jitter: Duration,
+    /// The longest any tick has taken
+    pub max_duration: Duration,
+    /// The number of deadlines missed entirely, because a tick overran into the next period
+    pub missed: usize,
+}
+
+/// Calls a tick function at a fixed rate
+///
+/// Deadlines are fixed multiples of the period from the first tick, so the rate doesn't drift. If
+/// a tick overruns its period, any deadlines it overran are skipped. Between ticks the loop
+/// waits with an [`IdleStrategy`], which defaults to sleeping the thread with `std` and to
+/// spinning without it, and which might instead sleep the core until a timer interrupt on an
+/// embedded target. The strategy may return early,
+/// e.g. on any interrupt; the loop just idles again until the deadline.
+///
+/// ```ignore
+/// let mut main_loop = MainLoop::every(Duration::from_millis(1), StdClock::new());
+/// loop {
+///     main_loop.run_once(|| exec.as_mut().tick());
+///     if main_loop.stats().max_jitter > Duration::from_micros(200) {
+///         log::warn!("main loop jitter {:?}", main_loop.stats());
+///     }
+/// }
+/// ```
+pub struct MainLoop<C: Clock, I: IdleStrategy = DefaultIdle> {
+    clock: C,
+    period: Duration,
+    idle: I,
+    next_tick: Option<Duration>,
+    stats: LoopStats,
+}
+
+impl<C: Clock> MainLoop<C> {
+    pub fn every(period: Duration, clock: C) -> Self {
+        Self {
+            clock,
+            period,
+            idle: DefaultIdle::default(),
+            next_tick: None,
+            stats: LoopStats::default(),
+        }
+    }
+}
+
+impl<C: Clock, I: IdleStrategy> MainLoop<C, I> {
+    /// Set how to wait out the time remaining until the next tick
+    pub fn idle<J: IdleStrategy>(self, idle: J) -> MainLoop<C, J> {
+        MainLoop {
+            clock: self.clock,
+            period: self.period,
+            idle,
+            next_tick: self.next_tick,
+            stats: self.stats,
+        }
+    }
+
+    /// Wait for the next deadline, then call `tick`
+    ///
+    /// The first call ticks immediately, and sets the schedule for the rest. With a zero period,
+    /// ticks run back to back, and the jitter is the time between one tick ending and the next
+    /// starting.
+    pub fn run_once<R>(&mut self, tick: impl FnOnce() -> R) -> R {
+        let deadline = *self.next_tick.get_or_insert_with(|| self.clock.now());
+        loop {
+            let now = self.clock.now();
+            if now >= deadline {
+                break;
+            }
+            self.idle.idle(deadline - now);
+        }
+
+        let start = self.clock.now();
+        let result = tick();
+        let end = self.clock.now();
+
+        let stats = &mut self.stats;
+        stats.ticks += 1;
+        stats.jitter = start - deadline;
+        stats.max_jitter = stats.max_jitter.max(stats.jitter);
+        stats.max_duration = stats.max_duration.max(end - start);
+        let next = if self.period.is_zero() {
+            end
+        } else {
+            let next = next_deadline(deadline, self.period, end, Overrun::Skip);
+            let periods = (next - deadline).as_nanos() / self.period.as_nanos();
+            let missed = usize::try_from(periods).unwrap_or(usize::MAX).saturating_sub(1);
+            stats.missed = stats.missed.saturating_add(missed);
+            next
+        };
+        self.next_tick = Some(next);
+        result
+    }
+
+    pub fn stats(&self) -> LoopStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = LoopStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_main_loop_stats() {
+        let clock = MockClock::new();
+        // Idle by jumping the clock to just past the deadline, as a late timer interrupt would
+        let mut main_loop = MainLoop::every(10 * MS, &clock).idle(|remaining| {
+            clock.advance(remaining + MS / 10);
+        });
+        let mut starts = Vec::new();
+        for duration in [2, 1, 25, 1] {
+            main_loop.run_once(|| {
+                starts.push(clock.now());
+                clock.advance(duration * MS);
+            });
+        }
+        let late = Duration::from_micros(100);
+        assert_eq!(starts, [Duration::ZERO, 10 * MS + late, 20 * MS + late, 50 * MS + late]);
+        assert_eq!(main_loop.stats(), LoopStats {
+            ticks: 4,
+            jitter: late,
+            max_jitter: late,
+            max_duration: 25 * MS,
+            missed: 2,
+        });
+    }
+
+    #[test]
+    fn test_zero_period_runs_back_to_back() {
+        let clock = MockClock::new();
+        let mut main_loop = MainLoop::every(Duration::ZERO, &clock).idle(|_| unreachable!());
+        for _ in 0..3 {
+            main_loop.run_once(|| clock.advance(MS));
+        }
+        let stats = main_loop.stats();
+        assert_eq!((stats.ticks, stats.max_jitter, stats.missed), (3, Duration::ZERO, 0));
+    }
+}
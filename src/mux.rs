@@ -0,0 +1,316 @@
+//! Multiplexing several producer machines into one framed stream, and splitting it up again
+
+use core::cell::{Cell, RefCell};
+
+use crate::{yield_now, AsyncSerialize, ByteSink};
+
+/// Error returned by [`MuxStream::send`] for a value which doesn't fit in the stream's buffer,
+/// and by [`write_frame`](crate::write_frame) for a value too long for its length prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTooLong;
+
+impl core::fmt::Display for FrameTooLong {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("frame too long")
+    }
+}
+
+impl core::error::Error for FrameTooLong {}
+
+/// One producer's input to [`mux`], holding a frame of up to `N` bytes
+///
+/// Frame lengths are sent as a `u16`, so `N` can be at most `u16::MAX`; larger buffers fail to
+/// compile.
+pub struct MuxStream<const N: usize> {
+    buf: RefCell<[u8; N]>,
+    /// The length of the frame waiting to be sent, if there is one
+    pending: Cell<Option<usize>>,
+    closed: Cell<bool>,
+}
+
+impl<const N: usize> MuxStream<N> {
+    pub const fn new() -> Self {
+        const { assert!(N <= u16::MAX as usize, "frame lengths are sent as a u16") };
+        Self { buf: RefCell::new([0; N]), pending: Cell::new(None), closed: Cell::new(false) }
+    }
+
+    /// Send `value` as one frame, waiting until the previous frame has been sent
+    // The buffer is only borrowed by the producer while no frame is pending, and by the mux while
+    // one is, so the borrows held across awaits here and in `mux` can't conflict
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub async fn send<T: AsyncSerialize + ?Sized>(&self, value: &T) -> Result<(), FrameTooLong> {
+        let len = value.serialized_len();
+        if len > N {
+            return Err(FrameTooLong);
+        }
+        while self.pending.get().is_some() {
+            yield_now().await;
+        }
+        let mut sink = SliceSink { buf: &mut *self.buf.borrow_mut(), pos: 0 };
+        value.serialize(&mut sink).await;
+        self.pending.set(Some(sink.pos));
+        Ok(())
+    }
+
+    /// Mark the stream as finished, once its last frame has been sent
+    pub fn close(&self) {
+        self.closed.set(true);
+    }
+}
+
+impl<const N: usize> Default for MuxStream<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A sink over a fixed buffer, which is known to be long enough for the value written to it
+struct SliceSink<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl ByteSink for SliceSink<'_> {
+    async fn write(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+}
+
+/// Interleave the frames sent on `streams` into `sink`, one frame at a time
+///
+/// Each frame is written as the stream's index in `streams` (one byte), a little-endian `u16`
+/// payload length, and then the payload. Streams are served round-robin, so a busy producer can't
+/// starve the others. The producers run as separate machines, which must be polled alongside the
+/// mux (e.g. within a [`scope`](crate::scope())). The mux completes once every stream is closed
+/// and has no frame left to send.
+#[allow(clippy::await_holding_refcell_ref)]
+pub async fn mux<const N: usize>(streams: &[&MuxStream<N>], sink: &mut impl ByteSink) {
+    assert!(streams.len() <= 256, "stream IDs are one byte");
+    let mut next = 0;
+    loop {
+        let mut sent = false;
+        for i in (next..streams.len()).chain(0..next) {
+            let stream = streams[i];
+            if let Some(len) = stream.pending.get() {
+                sink.write(&[i as u8]).await;
+                // `MuxStream::new` checks that `N`, and so `len`, fits in a u16
+                sink.write(&(len as u16).to_le_bytes()).await;
+                sink.write(&stream.buf.borrow()[..len]).await;
+                stream.pending.set(None);
+                next = (i + 1) % streams.len();
+                sent = true;
+                break;
+            }
+        }
+        if !sent {
+            if streams.iter().all(|s| s.closed.get()) {
+                return;
+            }
+            yield_now().await;
+        }
+    }
+}
+
+/// Errors which end a [`demux`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemuxError {
+    /// A frame was tagged with a stream ID which has no stream
+    UnknownStream(u8),
+    /// A frame was longer than the buffer of the stream it was sent on
+    FrameTooLong(u8),
+    /// The input ended part way through a frame
+    Truncated,
+}
+
+impl core::fmt::Display for DemuxError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DemuxError::UnknownStream(id) => write!(f, "unknown stream {id}"),
+            DemuxError::FrameTooLong(id) => write!(f, "frame too long for stream {id}"),
+            DemuxError::Truncated => f.write_str("input ended part way through a frame"),
+        }
+    }
+}
+
+impl core::error::Error for DemuxError {}
+
+/// One consumer's output from [`demux`], holding a received frame of up to `N` bytes
+pub struct DemuxStream<const N: usize> {
+    buf: RefCell<[u8; N]>,
+    /// The length of the frame waiting to be received, if there is one
+    pending: Cell<Option<usize>>,
+    closed: Cell<bool>,
+}
+
+impl<const N: usize> DemuxStream<N> {
+    pub const fn new() -> Self {
+        Self { buf: RefCell::new([0; N]), pending: Cell::new(None), closed: Cell::new(false) }
+    }
+
+    /// Wait for the next frame, and pass its payload to `f`
+    ///
+    /// Returns `None` once the demux has finished and there are no more frames.
+    pub async fn recv<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        loop {
+            if let Some(len) = self.pending.get() {
+                let result = f(&self.buf.borrow()[..len]);
+                self.pending.set(None);
+                return Some(result);
+            }
+            if self.closed.get() {
+                return None;
+            }
+            yield_now().await;
+        }
+    }
+}
+
+impl<const N: usize> Default for DemuxStream<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Split a stream written by [`mux`] back into frames, passing each to its stream in `streams`
+///
+/// `rx` returns the next received byte, or `None` if no byte is currently available. If
+/// `end_of_input` then returns true, the demux completes, with [`DemuxError::Truncated`] if that
+/// happened part way through a frame; otherwise it yields until it is polled again. A frame is
+/// only received into a stream once the stream's consumer has taken the previous one, so a slow
+/// consumer holds up the whole input rather than losing frames.
+///
+/// Any error ends the demux, since the framing can't be trusted after it. Either way, all the
+/// streams are closed when it completes.
+pub async fn demux<const N: usize>(
+    mut rx: impl FnMut() -> Option<u8>,
+    end_of_input: impl Fn() -> bool,
+    streams: &[&DemuxStream<N>],
+) -> Result<(), DemuxError> {
+    let result = demux_frames(&mut rx, end_of_input, streams).await;
+    for stream in streams {
+        stream.closed.set(true);
+    }
+    result
+}
+
+async fn demux_frames<const N: usize>(
+    mut rx: impl FnMut() -> Option<u8>,
+    end_of_input: impl Fn() -> bool,
+    streams: &[&DemuxStream<N>],
+) -> Result<(), DemuxError> {
+    let mut header = [0; 3];
+    loop {
+        for (i, b) in header.iter_mut().enumerate() {
+            *b = loop {
+                match rx() {
+                    Some(byte) => break byte,
+                    None if end_of_input() => {
+                        return if i == 0 { Ok(()) } else { Err(DemuxError::Truncated) };
+                    }
+                    None => yield_now().await,
+                }
+            };
+        }
+        let id = header[0];
+        let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+        let stream = streams.get(id as usize).ok_or(DemuxError::UnknownStream(id))?;
+        if len > N {
+            return Err(DemuxError::FrameTooLong(id));
+        }
+        while stream.pending.get().is_some() {
+            yield_now().await;
+        }
+        let mut pos = 0;
+        while pos < len {
+            match rx() {
+                Some(byte) => {
+                    stream.buf.borrow_mut()[pos] = byte;
+                    pos += 1;
+                }
+                None if end_of_input() => return Err(DemuxError::Truncated),
+                None => yield_now().await,
+            }
+        }
+        stream.pending.set(Some(len));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{poll_once, scope};
+    use core::pin::pin;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_mux_round_robin() {
+        let config = MuxStream::<4>::new();
+        let telemetry = MuxStream::<4>::new();
+        let mut out = Vec::new();
+        {
+            let mut fut = pin!(scope(async |s| {
+                s.spawn(async {
+                    config.send(&[1u8, 2]).await.unwrap();
+                    assert_eq!(config.send(&[0u8; 5]).await, Err(FrameTooLong));
+                    config.close();
+                });
+                s.spawn(async {
+                    for i in 0..3u16 {
+                        telemetry.send(&i).await.unwrap();
+                    }
+                    telemetry.close();
+                });
+                mux(&[&config, &telemetry], &mut out).await;
+            }));
+            while poll_once(fut.as_mut()).is_none() {}
+        }
+        assert_eq!(out, [
+            0, 2, 0, 1, 2, // config
+            1, 2, 0, 0, 0, // telemetry 0
+            1, 2, 0, 1, 0, // telemetry 1
+            1, 2, 0, 2, 0, // telemetry 2
+        ]);
+    }
+
+    #[test]
+    fn test_demux_to_handlers() {
+        // A config frame, two telemetry frames, and then a frame for a stream which doesn't exist
+        let input = RefCell::new(VecDeque::from([
+            0, 2, 0, 1, 2, //
+            1, 1, 0, 7, //
+            1, 1, 0, 8, //
+            2, 0, 0,
+        ]));
+        let config = DemuxStream::<4>::new();
+        let telemetry = DemuxStream::<4>::new();
+        let mut received = Vec::new();
+        let result = {
+            let mut fut = pin!(scope(async |s| {
+                s.spawn(async {
+                    assert_eq!(config.recv(|frame| frame.to_vec()).await, Some(vec![1, 2]));
+                    assert_eq!(config.recv(|_| ()).await, None);
+                });
+                s.spawn(async {
+                    while let Some(frame) = telemetry.recv(|frame| frame[0]).await {
+                        received.push(frame);
+                    }
+                });
+                let rx = || input.borrow_mut().pop_front();
+                demux(rx, || true, &[&config, &telemetry]).await
+            }));
+            loop {
+                if let Some(result) = poll_once(fut.as_mut()) {
+                    break result;
+                }
+            }
+        };
+        assert_eq!(result, Err(DemuxError::UnknownStream(2)));
+        assert_eq!(received, [7, 8]);
+
+        let input = RefCell::new(VecDeque::from([0, 2, 0, 1]));
+        let rx = || input.borrow_mut().pop_front();
+        let result = poll_once(pin!(demux(rx, || true, &[&DemuxStream::<4>::new()])));
+        assert_eq!(result, Some(Err(DemuxError::Truncated)));
+    }
+}
@@ -0,0 +1,160 @@
+//! Timed output sequences, such as LED patterns, buzzer melodies or motion profiles
+
+use core::time::Duration;
+
+use crate::{Clock, Coroutine, CoroutineState};
+
+/// The most steps [`PatternPlayer::exec`] will consume in one call
+///
+/// This bounds the time spent in a call. A repeating pattern whose holds are all zero never
+/// reaches a step that is still in the future, and would otherwise spin forever.
+pub const MAX_STEPS_PER_EXEC: usize = 64;
+
+/// Replays a sequence of timed output steps produced by a coroutine
+///
+/// The coroutine yields `(value, hold)` steps: `value` becomes the current output, and the next
+/// step is not requested until it has been held for `hold`. Step times are accumulated from the
+/// start of the previous step rather than from when the player happened to be polled, so a
+/// pattern does not drift when the player is polled late. The pattern ends when the coroutine
+/// returns; write the body as a `loop` for a repeating pattern.
+///
+/// ```ignore
+/// async fn blink(y: &Yielder<(bool, Duration)>) {
+///     loop {
+///         y.yield_value((true, Duration::from_millis(100))).await;
+///         y.yield_value((false, Duration::from_millis(900))).await;
+///     }
+/// }
+///
+/// let yielder = Yielder::new();
+/// let body = pin!(blink(&yielder));
+/// let mut player = PatternPlayer::new(Coroutine::new(body, &yielder), clock);
+/// loop {
+///     if let Some(on) = player.exec() {
+///         led.set(on);
+///     }
+/// }
+/// ```
+pub struct PatternPlayer<'a, 'y, F, T, C>
+where
+    F: Future<Output = ()>,
+    C: Clock,
+{
+    co: Coroutine<'a, 'y, F, (T, Duration)>,
+    clock: C,
+    current: Option<T>,
+    next_step_at: Option<Duration>,
+    finished: bool,
+}
+
+impl<'a, 'y, F, T, C> PatternPlayer<'a, 'y, F, T, C>
+where
+    F: Future<Output = ()>,
+    C: Clock,
+{
+    pub fn new(co: Coroutine<'a, 'y, F, (T, Duration)>, clock: C) -> Self {
+        Self { co, clock, current: None, next_step_at: None, finished: false }
+    }
+
+    /// Advance the pattern
+    ///
+    /// Returns `Some(value)` when a new step has started and its value should be applied to the
+    /// output, or `None` if the output should be left unchanged. If the player is polled so late
+    /// that several steps have elapsed, only the latest value is returned. At most
+    /// [`MAX_STEPS_PER_EXEC`] steps are consumed per call; any further elapsed steps are caught
+    /// up on the following calls.
+    pub fn exec(&mut self) -> Option<&T> {
+        let now = self.clock.now();
+        let mut changed = false;
+        for _ in 0..MAX_STEPS_PER_EXEC {
+            if self.finished {
+                break;
+            }
+            if self.next_step_at.is_some_and(|next| now < next) {
+                break;
+            }
+            match self.co.resume() {
+                CoroutineState::Yielded((value, hold)) => {
+                    let start = self.next_step_at.unwrap_or(now);
+                    self.next_step_at = Some(start + hold);
+                    self.current = Some(value);
+                    changed = true;
+                }
+                CoroutineState::Pending => break,
+                CoroutineState::Complete(()) => self.finished = true,
+            }
+        }
+        if changed {
+            self.current.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// The value of the current step, if the pattern has started
+    pub fn current(&self) -> Option<&T> {
+        self.current.as_ref()
+    }
+
+    /// True once the coroutine has returned
+    ///
+    /// The value of the last step remains available from [`PatternPlayer::current`].
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockClock, Yielder};
+    use core::pin::pin;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    async fn pattern(y: &Yielder<(u8, Duration)>) {
+        y.yield_value((1, 10 * MS)).await;
+        y.yield_value((2, Duration::ZERO)).await;
+        y.yield_value((3, 20 * MS)).await;
+    }
+
+    #[test]
+    fn test_pattern_player() {
+        let clock = MockClock::new();
+        let yielder = Yielder::new();
+        let body = pin!(pattern(&yielder));
+        let mut player = PatternPlayer::new(Coroutine::new(body, &yielder), &clock);
+
+        assert_eq!(player.exec(), Some(&1));
+        assert_eq!(player.exec(), None);
+        // Late poll: the zero-length step is skipped over, and timing is kept from step start
+        clock.advance(15 * MS);
+        assert_eq!(player.exec(), Some(&3));
+        clock.advance(14 * MS);
+        assert_eq!(player.exec(), None);
+        assert!(!player.is_finished());
+        clock.advance(MS);
+        assert_eq!(player.exec(), None);
+        assert!(player.is_finished());
+        assert_eq!(player.current(), Some(&3));
+    }
+
+    #[test]
+    fn test_zero_hold_pattern_returns() {
+        async fn zeros(y: &Yielder<(u8, Duration)>) {
+            let mut i = 0u8;
+            loop {
+                y.yield_value((i, Duration::ZERO)).await;
+                i = i.wrapping_add(1);
+            }
+        }
+
+        let clock = MockClock::new();
+        let yielder = Yielder::new();
+        let body = pin!(zeros(&yielder));
+        let mut player = PatternPlayer::new(Coroutine::new(body, &yielder), &clock);
+
+        assert_eq!(player.exec(), Some(&(MAX_STEPS_PER_EXEC as u8 - 1)));
+        assert_eq!(player.exec(), Some(&(2 * MAX_STEPS_PER_EXEC as u8 - 1)));
+    }
+}
@@ -0,0 +1,175 @@
+//! Machines which are restarted on a fixed period
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use core::pin::Pin;
+use core::time::Duration;
+
+#[cfg(feature = "alloc")]
+use crate::{poll_once, Clock};
+
+/// What a [`Periodic`] does when a run finishes after one or more later start times have passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overrun {
+    /// Drop the missed runs, and wait for the next start time which is still in the future
+    #[default]
+    Skip,
+    /// Start the missed runs back to back until the schedule has caught up
+    CatchUp,
+}
+
+/// Creates and polls a fresh machine from a factory once per period
+///
+/// Start times are scheduled at fixed multiples of the period from the first start, so the
+/// schedule does not drift with poll latency. Runs never overlap: if a run is still in progress
+/// when the next one is due, the next one starts once it completes, according to the
+/// [`Overrun`] policy.
+///
+/// ```ignore
+/// let mut sampler = Periodic::every(Duration::from_millis(100), clock, || read_sensor(&bus));
+/// loop {
+///     if let Some(sample) = sampler.exec() {
+///         db.store_value(sample);
+///     }
+/// }
+/// ```
+#[cfg(feature = "alloc")]
+pub struct Periodic<G, F, C>
+where
+    G: FnMut() -> F,
+    F: Future,
+    C: Clock,
+{
+    factory: G,
+    clock: C,
+    period: Duration,
+    overrun: Overrun,
+    next_start: Option<Duration>,
+    current: Option<Pin<Box<F>>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<G, F, C> Periodic<G, F, C>
+where
+    G: FnMut() -> F,
+    F: Future,
+    C: Clock,
+{
+    /// Run a machine created by `factory` every `period`, with the first run starting on the first
+    /// call to [`Periodic::exec`]
+    pub fn every(period: Duration, clock: C, factory: G) -> Self {
+        Self {
+            factory,
+            clock,
+            period,
+            overrun: Overrun::default(),
+            next_start: None,
+            current: None,
+        }
+    }
+
+    /// Set the overrun policy
+    pub fn overrun(mut self, overrun: Overrun) -> Self {
+        self.overrun = overrun;
+        self
+    }
+
+    /// Start a run if one is due, and poll the current run once
+    ///
+    /// Returns the output of the current run if it completed during this call.
+    pub fn exec(&mut self) -> Option<F::Output> {
+        let now = self.clock.now();
+        if self.current.is_none() {
+            let next_start = *self.next_start.get_or_insert(now);
+            if now >= next_start {
+                self.current = Some(Box::pin((self.factory)()));
+                self.schedule_next(next_start, now);
+            }
+        }
+
+        let result = poll_once(self.current.as_mut()?.as_mut());
+        if result.is_some() {
+            self.current = None;
+        }
+        result
+    }
+
+    /// True if a run is currently in progress
+    pub fn is_running(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn schedule_next(&mut self, started: Duration, now: Duration) {
+        self.next_start = Some(next_deadline(started, self.period, now, self.overrun));
+    }
+}
+
+/// The deadline following `previous` on a fixed `period` schedule, applying the `overrun`
+/// policy to any deadlines which have already passed by `now`
+pub(crate) fn next_deadline(
+    previous: Duration,
+    period: Duration,
+    now: Duration,
+    overrun: Overrun,
+) -> Duration {
+    let next = previous + period;
+    if overrun == Overrun::Skip && next <= now && !period.is_zero() {
+        // The first deadline after `now`, found from how far `now` is into its period rather than
+        // by counting the missed periods, which may be too many to count in a `u32`
+        let into_period = (now - next).as_nanos() % period.as_nanos();
+        let into_period = Duration::new(
+            (into_period / 1_000_000_000) as u64,
+            (into_period % 1_000_000_000) as u32,
+        );
+        now.saturating_add(period - into_period)
+    } else {
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+    use core::cell::Cell;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_periodic_skip_and_catch_up() {
+        for (overrun, expected_starts) in [(Overrun::Skip, 3), (Overrun::CatchUp, 4)] {
+            let clock = MockClock::new();
+            let starts = Cell::new(0);
+            let mut periodic = Periodic::every(10 * MS, &clock, || {
+                starts.set(starts.get() + 1);
+                async {}
+            })
+            .overrun(overrun);
+
+            assert_eq!(periodic.exec(), Some(()));
+            clock.advance(9 * MS);
+            assert_eq!(periodic.exec(), None);
+            clock.advance(MS);
+            assert_eq!(periodic.exec(), Some(()));
+            // Stall past two start times
+            clock.advance(25 * MS);
+            for _ in 0..4 {
+                periodic.exec();
+            }
+            assert_eq!(starts.get(), expected_starts, "{overrun:?}");
+        }
+    }
+
+    #[test]
+    fn test_skip_after_long_stall() {
+        let period = Duration::from_nanos(3);
+        let now = Duration::from_secs(100_000);
+        // Far more than `u32::MAX` periods have been missed
+        let next = next_deadline(Duration::ZERO, period, now, Overrun::Skip);
+        assert!(next > now && next <= now + period, "{next:?}");
+        assert_eq!(next.as_nanos() % 3, 0);
+        assert_eq!(next_deadline(Duration::ZERO, 10 * MS, 20 * MS, Overrun::Skip), 30 * MS);
+        assert_eq!(next_deadline(Duration::ZERO, 10 * MS, 25 * MS, Overrun::Skip), 30 * MS);
+    }
+}
@@ -0,0 +1,46 @@
+//! Saving and restoring the state of explicit state machines
+
+/// Error returned when saved state can't be restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidState;
+
+impl core::fmt::Display for InvalidState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("invalid saved state")
+    }
+}
+
+impl core::error::Error for InvalidState {}
+
+/// A state machine whose state can be saved, e.g. to flash, and restored after a reboot
+///
+/// This is the one thing an explicit, hand-written state machine can do which an async one
+/// can't: its state is a plain value which the author chose, so it can be written out and read
+/// back. An async machine's state is a compiler-generated future, holding borrows and
+/// references into its own stack frame, and there is no way to take it apart or rebuild it.
+///
+/// The two approaches complement each other. Workflows which must survive power loss, such as a
+/// long transfer or a multi-step provisioning sequence, keep their resumable position in an
+/// explicit machine implementing this trait. Everything else is written as async code, and
+/// recovers by starting again from a point the explicit state describes, e.g. by skipping the
+/// bytes which were already sent. The state covers only the machine's progress; its inputs,
+/// such as the data being transferred, are supplied again when the machine is recreated, and
+/// [`PersistentStateMachine::restore`] is called on the new machine.
+pub trait PersistentStateMachine {
+    /// The number of bytes [`PersistentStateMachine::save_state`] writes
+    const STATE_LEN: usize;
+
+    /// Write the machine's state to the start of `buf`
+    ///
+    /// Panics if `buf` is shorter than [`PersistentStateMachine::STATE_LEN`].
+    fn save_state(&self, buf: &mut [u8]);
+
+    /// Resume from state saved by a machine over the same inputs
+    ///
+    /// The state is read from the start of `bytes`, and anything after the first
+    /// [`PersistentStateMachine::STATE_LEN`] bytes is ignored, so a whole flash page can be passed
+    /// in. The saved state is checked against this machine's inputs, and rejected if it is too
+    /// short or doesn't describe a valid position in them, in which case the machine is left
+    /// unchanged.
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), InvalidState>;
+}
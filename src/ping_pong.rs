@@ -0,0 +1,146 @@
+//! Double-buffered transfers between a producing machine and a consumer
+
+use core::cell::{Cell, Ref, RefCell};
+
+use crate::{yield_now, ByteSink};
+
+/// A pair of `N` byte buffers, filled by a machine while the consumer drains the other
+///
+/// The producer writes with [`PingPong::write`] (or through the [`ByteSink`] impl), which copies
+/// as much as fits into the current buffer in one go, and hands the buffer over once it is full.
+/// It only yields when both buffers are waiting to be drained. The consumer takes a full buffer
+/// with [`PingPong::ready`], and returns it with [`PingPong::release`] once it is done with it,
+/// which for a DMA transfer would be on its completion interrupt.
+///
+/// ```
+/// use async_state_machine_example::{poll_once, PingPong};
+/// use core::pin::pin;
+///
+/// let buf = PingPong::<4>::new();
+/// let mut fut = pin!(async {
+///     buf.write(b"hello world").await;
+///     buf.flush();
+/// });
+/// // Both buffers are filled in the first poll, after which the producer has to wait
+/// assert_eq!(poll_once(fut.as_mut()), None);
+/// assert_eq!(&*buf.ready().unwrap(), b"hell");
+/// buf.release();
+/// // The rest fits in the released buffer
+/// assert_eq!(poll_once(fut.as_mut()), Some(()));
+/// assert_eq!(&*buf.ready().unwrap(), b"o wo");
+/// buf.release();
+/// assert_eq!(&*buf.ready().unwrap(), b"rld");
+/// ```
+pub struct PingPong<const N: usize> {
+    bufs: [RefCell<[u8; N]>; 2],
+    /// The length of the data in each buffer once it has been handed to the consumer
+    ready: [Cell<Option<usize>>; 2],
+    write_buf: Cell<usize>,
+    write_pos: Cell<usize>,
+    read_buf: Cell<usize>,
+}
+
+impl<const N: usize> PingPong<N> {
+    pub const fn new() -> Self {
+        Self {
+            bufs: [RefCell::new([0; N]), RefCell::new([0; N])],
+            ready: [Cell::new(None), Cell::new(None)],
+            write_buf: Cell::new(0),
+            write_pos: Cell::new(0),
+            read_buf: Cell::new(0),
+        }
+    }
+
+    /// Copy `bytes` into the buffers, waiting for the consumer whenever both are full
+    pub async fn write(&self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let index = self.write_buf.get();
+            if self.ready[index].get().is_some() {
+                yield_now().await;
+                continue;
+            }
+            let pos = self.write_pos.get();
+            let n = bytes.len().min(N - pos);
+            self.bufs[index].borrow_mut()[pos..pos + n].copy_from_slice(&bytes[..n]);
+            self.write_pos.set(pos + n);
+            bytes = &bytes[n..];
+            if pos + n == N {
+                self.flush();
+            }
+        }
+    }
+
+    /// Hand the current buffer to the consumer, even if it is only partially full
+    pub fn flush(&self) {
+        let pos = self.write_pos.get();
+        if pos > 0 {
+            let index = self.write_buf.get();
+            self.ready[index].set(Some(pos));
+            self.write_buf.set(index ^ 1);
+            self.write_pos.set(0);
+        }
+    }
+
+    /// The next buffer to be drained, if the producer has filled one
+    pub fn ready(&self) -> Option<Ref<'_, [u8]>> {
+        let index = self.read_buf.get();
+        let len = self.ready[index].get()?;
+        Some(Ref::map(self.bufs[index].borrow(), |buf| &buf[..len]))
+    }
+
+    /// Return the buffer from [`PingPong::ready`] to the producer
+    ///
+    /// Does nothing if there is no ready buffer.
+    pub fn release(&self) {
+        let index = self.read_buf.get();
+        if self.ready[index].take().is_some() {
+            self.read_buf.set(index ^ 1);
+        }
+    }
+}
+
+impl<const N: usize> Default for PingPong<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ByteSink for &PingPong<N> {
+    async fn write(&mut self, bytes: &[u8]) {
+        PingPong::write(self, bytes).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll_once;
+    use core::pin::pin;
+
+    #[test]
+    fn test_polls_per_buffer() {
+        let data: Vec<u8> = (0..=255).collect();
+        let buf = PingPong::<32>::new();
+        let mut out = Vec::new();
+        let mut polls = 0;
+        {
+            let mut fut = pin!(async {
+                buf.write(&data).await;
+                buf.flush();
+            });
+            let mut done = false;
+            while !done {
+                polls += 1;
+                done = poll_once(fut.as_mut()).is_some();
+                // Drain one buffer per poll
+                out.extend_from_slice(&buf.ready().unwrap());
+                buf.release();
+            }
+        }
+        out.extend_from_slice(&buf.ready().unwrap());
+        buf.release();
+        assert_eq!(out, data);
+        // One poll for each buffer after the first
+        assert_eq!(polls, 7);
+    }
+}
@@ -0,0 +1,87 @@
+//! Progress reporting from long-running machines
+
+use core::cell::Cell;
+
+/// A snapshot of how far through its work a machine is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgressReport {
+    pub done: u32,
+    pub total: u32,
+}
+
+impl ProgressReport {
+    pub const fn new(done: u32, total: u32) -> Self {
+        Self { done, total }
+    }
+
+    /// Completion as a whole percentage, from 0 to 100
+    ///
+    /// A total of zero means the amount of work is not known yet, and reports 0%.
+    pub fn percent(&self) -> u8 {
+        if self.total == 0 {
+            return 0;
+        }
+        (u64::from(self.done.min(self.total)) * 100 / u64::from(self.total)) as u8
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total > 0 && self.done >= self.total
+    }
+}
+
+/// A progress value shared between an async body and the code polling it
+///
+/// The body updates it as it goes, and the caller reads it between polls, e.g. to show "erasing
+/// 43%" on a display. For machines written as a [`Coroutine`](crate::Coroutine), the
+/// [`ProgressReport`] can instead be used directly as (part of) the yield type, so each yield
+/// carries the current progress.
+#[derive(Debug, Default)]
+pub struct Progress {
+    report: Cell<ProgressReport>,
+}
+
+impl Progress {
+    pub const fn new() -> Self {
+        Self { report: Cell::new(ProgressReport::new(0, 0)) }
+    }
+
+    pub fn set(&self, done: u32, total: u32) {
+        self.report.set(ProgressReport::new(done, total));
+    }
+
+    /// Advance the completed count by `n`, keeping the total
+    pub fn advance(&self, n: u32) {
+        let report = self.report.get();
+        self.set(report.done.saturating_add(n), report.total);
+    }
+
+    pub fn get(&self) -> ProgressReport {
+        self.report.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll_once;
+    use core::pin::pin;
+    use futures::pending;
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress::new();
+        let mut fut = pin!(async {
+            for i in 0..3 {
+                progress.set(i, 3);
+                pending!();
+            }
+        });
+
+        assert_eq!(poll_once(fut.as_mut()), None);
+        assert_eq!(poll_once(fut.as_mut()), None);
+        assert_eq!(progress.get(), ProgressReport::new(1, 3));
+        assert_eq!(progress.get().percent(), 33);
+        progress.advance(5);
+        assert_eq!(progress.get().percent(), 100);
+    }
+}
@@ -0,0 +1,96 @@
+//! Fixed-capacity queues for passing events into a machine
+
+use core::cell::Cell;
+
+use crate::yield_now;
+
+/// A first-in, first-out queue of up to `N` events
+///
+/// This is the multi-slot counterpart of [`Mailbox`](crate::Mailbox), for producers such as
+/// input scanners which may report several events between polls of the consumer. The storage is
+/// inline, with no allocation. Pushing to a full queue fails rather than overwriting, so the
+/// oldest events are the ones kept.
+pub struct EventQueue<T, const N: usize> {
+    items: [Cell<Option<T>>; N],
+    head: Cell<usize>,
+    len: Cell<usize>,
+}
+
+impl<T, const N: usize> EventQueue<T, N> {
+    pub const fn new() -> Self {
+        Self { items: [const { Cell::new(None) }; N], head: Cell::new(0), len: Cell::new(0) }
+    }
+
+    /// Add `event` to the back of the queue, or hand it back if the queue is full
+    pub fn push(&self, event: T) -> Result<(), T> {
+        let len = self.len.get();
+        if len == N {
+            return Err(event);
+        }
+        self.items[(self.head.get() + len) % N].set(Some(event));
+        self.len.set(len + 1);
+        Ok(())
+    }
+
+    /// Take the event at the front of the queue, if there is one
+    pub fn try_pop(&self) -> Option<T> {
+        if self.len.get() == 0 {
+            return None;
+        }
+        let head = self.head.get();
+        self.head.set((head + 1) % N);
+        self.len.set(self.len.get() - 1);
+        self.items[head].take()
+    }
+
+    /// Wait for an event to be pushed, and take it
+    pub async fn recv(&self) -> T {
+        loop {
+            if let Some(event) = self.try_pop() {
+                return event;
+            }
+            yield_now().await
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T, const N: usize> Default for EventQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll_once;
+    use core::pin::pin;
+
+    #[test]
+    fn test_queue_wraps_and_rejects_when_full() {
+        let queue = EventQueue::<u32, 2>::new();
+        let mut fut = pin!(async {
+            let a = queue.recv().await;
+            let b = queue.recv().await;
+            (a, b)
+        });
+        assert_eq!(poll_once(fut.as_mut()), None);
+
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert_eq!(queue.push(3), Err(3));
+        assert_eq!(queue.try_pop(), Some(1));
+        queue.push(3).unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(poll_once(fut.as_mut()), Some((2, 3)));
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+//! Limiting how often a machine is polled
+
+use core::time::Duration;
+
+use crate::{Clock, Machine, Step};
+
+/// Forwards polls to the inner machine at most once per `interval`
+///
+/// This is useful when an expensive machine sits inside a much faster outer loop. Calls which
+/// arrive before the interval has elapsed since the last forwarded poll return
+/// [`Step::Skipped`] without touching the inner machine. The first call is always forwarded.
+pub struct RateLimited<M: Machine, C: Clock> {
+    inner: M,
+    clock: C,
+    interval: Duration,
+    last_poll: Option<Duration>,
+}
+
+impl<M: Machine, C: Clock> RateLimited<M, C> {
+    pub fn new(inner: M, interval: Duration, clock: C) -> Self {
+        Self { inner, clock, interval, last_poll: None }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+impl<M: Machine, C: Clock> Machine for RateLimited<M, C> {
+    type Output = M::Output;
+
+    fn exec_step(&mut self) -> Step<M::Output> {
+        let now = self.clock.now();
+        if self.last_poll.is_some_and(|last| now - last < self.interval) {
+            return Step::Skipped;
+        }
+        self.last_poll = Some(now);
+        self.inner.exec_step()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AsyncStateMachine, MockClock};
+    use core::cell::Cell;
+    use core::pin::pin;
+    use futures::pending;
+
+    #[test]
+    fn test_rate_limited() {
+        let clock = MockClock::new();
+        let polls = Cell::new(0);
+        let future = pin!(async {
+            loop {
+                polls.set(polls.get() + 1);
+                pending!()
+            }
+        });
+        let interval = Duration::from_millis(10);
+        let mut fsm = RateLimited::new(AsyncStateMachine::new(future), interval, &clock);
+
+        assert_eq!(fsm.exec_step(), Step::Stalled);
+        for _ in 0..9 {
+            clock.advance(Duration::from_millis(1));
+            assert_eq!(fsm.exec_step(), Step::Skipped);
+        }
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(fsm.exec_step(), Step::Stalled);
+        assert_eq!(polls.get(), 2);
+    }
+}
@@ -0,0 +1,119 @@
+//! Structured concurrency: child machines bound to a scope
+
+use alloc::{boxed::Box, vec::Vec};
+use core::cell::RefCell;
+use core::pin::{pin, Pin};
+use core::task::Poll;
+
+use crate::{Completion, JoinHandle};
+
+type Child<'s> = Pin<Box<dyn Future<Output = ()> + 's>>;
+
+/// A set of child machines, created by [`scope`]
+pub struct Scope<'s> {
+    children: RefCell<Vec<Child<'s>>>,
+}
+
+impl<'s> Scope<'s> {
+    /// Start a child machine
+    ///
+    /// The child is first polled the next time the scope is polled, after the scope's body.
+    pub fn spawn(&self, child: impl Future<Output = ()> + 's) {
+        self.children.borrow_mut().push(Box::pin(child));
+    }
+
+    /// Start a child machine whose output is stored in `completion`, and return a handle to it
+    ///
+    /// The handle can be awaited by the body or by other children to wait for this one.
+    pub fn spawn_joinable<T>(
+        &self,
+        completion: &'s Completion<T>,
+        child: impl Future<Output = T> + 's,
+    ) -> JoinHandle<'s, T> {
+        self.spawn(completion.watch(child));
+        completion.handle()
+    }
+
+    /// The number of children which have not completed yet
+    pub fn running(&self) -> usize {
+        self.children.borrow().len()
+    }
+}
+
+/// Run `body` with a [`Scope`] on which it can spawn child machines
+///
+/// Every time the scope future is polled, the body is polled and then each running child is
+/// polled once, so children advance in lock step with the parent without having to be joined
+/// by hand. The scope completes with the body's result once the body *and* all children have
+/// completed. If the scope future is dropped first, the body and all children are dropped with
+/// it, so no child outlives its scope either way.
+///
+/// ```
+/// use async_state_machine_example::{scope, yield_times, AsyncStateMachine};
+/// use core::cell::Cell;
+/// use core::pin::pin;
+///
+/// let done = Cell::new(0);
+/// let fut = pin!(scope(async |s| {
+///     for n in 1..=3 {
+///         let done = &done;
+///         s.spawn(async move {
+///             yield_times(n).await;
+///             done.set(done.get() + 1);
+///         });
+///     }
+///     "spawned"
+/// }));
+/// let mut fsm = AsyncStateMachine::new(fut);
+/// let result = loop {
+///     if let Some(result) = fsm.exec() {
+///         break result;
+///     }
+/// };
+/// assert_eq!((result, done.get()), ("spawned", 3));
+/// ```
+pub async fn scope<'s, R>(body: impl AsyncFnOnce(&Scope<'s>) -> R) -> R {
+    let scope = Scope { children: RefCell::new(Vec::new()) };
+    let mut body = pin!(body(&scope));
+    let mut result = None;
+    core::future::poll_fn(|cx| {
+        if result.is_none()
+            && let Poll::Ready(value) = body.as_mut().poll(cx)
+        {
+            result = Some(value);
+        }
+        scope.children.borrow_mut().retain_mut(|child| child.as_mut().poll(cx).is_pending());
+        if result.is_some() && scope.children.borrow().is_empty() {
+            Poll::Ready(result.take().unwrap())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{on_exit, poll_once, yield_now};
+    use core::cell::Cell;
+
+    #[test]
+    fn test_dropping_scope_cancels_children() {
+        let cancelled = Cell::new(false);
+        let mut fut = Box::pin(scope(async |s| {
+            s.spawn(async {
+                on_exit!(|| cancelled.set(true));
+                loop {
+                    yield_now().await;
+                }
+            });
+        }));
+
+        assert_eq!(poll_once(fut.as_mut()), None);
+        assert_eq!(poll_once(fut.as_mut()), None);
+        assert!(!cancelled.get());
+        drop(fut);
+        assert!(cancelled.get());
+    }
+}
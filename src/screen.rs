@@ -0,0 +1,102 @@
+//! Writing UI flows as coroutines which yield the screen to draw
+//!
+//! A UI flow is an async fn which [yields](crate::Yielder) the current [`Screen`] and then awaits
+//! the next [`Button`] from an [`EventQueue`]. The display task resumes the flow's
+//! [`Coroutine`](crate::Coroutine) once per refresh, and redraws whenever it yields a new screen,
+//! so nested menus and editors are written as ordinary nested calls rather than as a table of
+//! states.
+
+use crate::{EventQueue, Yielder};
+
+/// A button press, as delivered to a UI flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Up,
+    Down,
+    Select,
+    Back,
+}
+
+/// What the display should show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen<'a> {
+    /// A list of items, with one highlighted
+    Menu { title: &'a str, items: &'a [&'a str], selected: usize },
+    /// A number being edited
+    Value { label: &'a str, value: i32 },
+    /// A fixed message
+    Message(&'a str),
+}
+
+/// Yield `screen` for display, then wait for the next button press
+pub async fn show<S, B, const N: usize>(
+    yielder: &Yielder<S>,
+    screen: S,
+    buttons: &EventQueue<B, N>,
+) -> B {
+    yielder.yield_value(screen).await;
+    buttons.recv().await
+}
+
+/// Show a menu until an item is selected, returning its index, or `None` on [`Button::Back`]
+///
+/// Up and down move the highlight, wrapping at either end. `items` must not be empty.
+pub async fn menu<'a, const N: usize>(
+    yielder: &Yielder<Screen<'a>>,
+    title: &'a str,
+    items: &'a [&'a str],
+    buttons: &EventQueue<Button, N>,
+) -> Option<usize> {
+    let mut selected = 0;
+    loop {
+        match show(yielder, Screen::Menu { title, items, selected }, buttons).await {
+            Button::Up => selected = (selected + items.len() - 1) % items.len(),
+            Button::Down => selected = (selected + 1) % items.len(),
+            Button::Select => return Some(selected),
+            Button::Back => return None,
+        }
+    }
+}
+
+/// Edit a number within `range`, returning the new value, or `None` on [`Button::Back`]
+pub async fn edit_value<'a, const N: usize>(
+    yielder: &Yielder<Screen<'a>>,
+    label: &'a str,
+    mut value: i32,
+    range: core::ops::RangeInclusive<i32>,
+    buttons: &EventQueue<Button, N>,
+) -> Option<i32> {
+    loop {
+        match show(yielder, Screen::Value { label, value }, buttons).await {
+            Button::Up => value = (value + 1).min(*range.end()),
+            Button::Down => value = (value - 1).max(*range.start()),
+            Button::Select => return Some(value),
+            Button::Back => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coroutine, CoroutineState};
+    use core::pin::pin;
+
+    #[test]
+    fn test_menu_navigation() {
+        const ITEMS: &[&str] = &["a", "b", "c"];
+        let yielder = Yielder::new();
+        let buttons = EventQueue::<Button, 4>::new();
+        let body = pin!(menu(&yielder, "Main", ITEMS, &buttons));
+        let mut co = Coroutine::new(body, &yielder);
+        let menu = |selected| Screen::Menu { title: "Main", items: ITEMS, selected };
+
+        assert_eq!(co.resume(), CoroutineState::Yielded(menu(0)));
+        // Nothing to redraw until a button is pressed
+        assert_eq!(co.resume(), CoroutineState::Pending);
+        buttons.push(Button::Up).unwrap();
+        assert_eq!(co.resume(), CoroutineState::Yielded(menu(2)));
+        buttons.push(Button::Select).unwrap();
+        assert_eq!(co.resume(), CoroutineState::Complete(Some(2)));
+    }
+}
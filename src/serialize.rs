@@ -0,0 +1,324 @@
+//! Serialization of user types by machines which can stop at any byte
+
+use crate::{yield_now, FrameTooLong};
+
+/// A destination for serialized bytes
+// The returned futures are polled in place by the machine that owns them, so they have no need
+// to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait ByteSink {
+    async fn write(&mut self, bytes: &[u8]);
+}
+
+/// A type which can be serialized by a machine, one [`ByteSink::write`] at a time
+///
+/// This can be derived for structs, in which case the fields are serialized in declaration order
+/// with no padding or framing:
+///
+/// ```
+/// use async_state_machine_example::{AsyncSerialize, YieldingSink};
+/// use core::pin::pin;
+///
+/// #[derive(AsyncSerialize)]
+/// struct Sample {
+///     channel: u8,
+///     value: u16,
+/// }
+///
+/// let sample = Sample { channel: 3, value: 0x1234 };
+/// assert_eq!(sample.serialized_len(), 3);
+///
+/// let mut out = Vec::new();
+/// {
+///     let mut sink = YieldingSink::new(|b| out.push(b));
+///     let mut fut = pin!(sample.serialize(&mut sink));
+///     // One byte is written per poll
+///     while async_state_machine_example::poll_once(fut.as_mut()).is_none() {}
+/// }
+/// assert_eq!(out, [3, 0x34, 0x12]);
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait AsyncSerialize {
+    /// The number of bytes [`AsyncSerialize::serialize`] will write, e.g. for a length prefix
+    fn serialized_len(&self) -> usize;
+
+    async fn serialize(&self, sink: &mut impl ByteSink);
+}
+
+/// A [`ByteSink`] which passes each byte to a function, yielding after every one
+///
+/// This lets the caller collect the output one byte per poll, so that serialization can be broken
+/// up into chunks of any size.
+pub struct YieldingSink<F: FnMut(u8)> {
+    write_fn: F,
+}
+
+impl<F: FnMut(u8)> YieldingSink<F> {
+    pub fn new(write_fn: F) -> Self {
+        Self { write_fn }
+    }
+}
+
+impl<F: FnMut(u8)> ByteSink for YieldingSink<F> {
+    async fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            (self.write_fn)(b);
+            yield_now().await;
+        }
+    }
+}
+
+/// Collects the whole output without yielding
+#[cfg(feature = "alloc")]
+impl ByteSink for alloc::vec::Vec<u8> {
+    async fn write(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {$(
+        /// Serialized little-endian
+        impl AsyncSerialize for $t {
+            fn serialized_len(&self) -> usize {
+                size_of::<$t>()
+            }
+
+            async fn serialize(&self, sink: &mut impl ByteSink) {
+                sink.write(&self.to_le_bytes()).await;
+            }
+        }
+    )*};
+}
+
+impl_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl<T: AsyncSerialize> AsyncSerialize for [T] {
+    fn serialized_len(&self) -> usize {
+        self.iter().map(T::serialized_len).sum()
+    }
+
+    async fn serialize(&self, sink: &mut impl ByteSink) {
+        for item in self {
+            item.serialize(sink).await;
+        }
+    }
+}
+
+impl<T: AsyncSerialize, const N: usize> AsyncSerialize for [T; N] {
+    fn serialized_len(&self) -> usize {
+        self.as_slice().serialized_len()
+    }
+
+    async fn serialize(&self, sink: &mut impl ByteSink) {
+        self.as_slice().serialize(sink).await;
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: AsyncSerialize> AsyncSerialize for alloc::vec::Vec<T> {
+    fn serialized_len(&self) -> usize {
+        self.as_slice().serialized_len()
+    }
+
+    async fn serialize(&self, sink: &mut impl ByteSink) {
+        self.as_slice().serialize(sink).await;
+    }
+}
+
+/// Serialize `value` with a little-endian `u16` length prefix
+///
+/// Frames written this way can be read back with [`read_frame`]. A frame can be at most
+/// `u16::MAX` bytes long; a longer value is not written, and gives [`FrameTooLong`].
+pub async fn write_frame<T: AsyncSerialize + ?Sized>(
+    value: &T,
+    sink: &mut impl ByteSink,
+) -> Result<(), FrameTooLong> {
+    let len = u16::try_from(value.serialized_len()).map_err(|_| FrameTooLong)?;
+    len.serialize(sink).await;
+    value.serialize(sink).await;
+    Ok(())
+}
+
+/// Read the next length-prefixed frame from `rx` into `buf`, as written by [`write_frame`]
+///
+/// `rx` returns the next received byte, or `None` if no byte is currently available, in which
+/// case the future yields until it is polled again. The input can therefore arrive in chunks of
+/// any size, split anywhere in a frame. `buf` is cleared first, and holds the frame's payload
+/// (without the prefix) once the future completes.
+#[cfg(feature = "alloc")]
+pub async fn read_frame(mut rx: impl FnMut() -> Option<u8>, buf: &mut alloc::vec::Vec<u8>) {
+    buf.clear();
+    let mut len = [0; 2];
+    for b in &mut len {
+        *b = next_byte(&mut rx).await;
+    }
+    let len = u16::from_le_bytes(len) as usize;
+    while buf.len() < len {
+        buf.push(next_byte(&mut rx).await);
+    }
+}
+
+#[cfg(feature = "alloc")]
+async fn next_byte(mut rx: impl FnMut() -> Option<u8>) -> u8 {
+    loop {
+        match rx() {
+            Some(byte) => return byte,
+            None => yield_now().await,
+        }
+    }
+}
+
+/// A fixed-size buffer which text can be formatted into with [`core::fmt::Write`]
+///
+/// Formatting more than `N` bytes fails with [`core::fmt::Error`], keeping the text which fitted.
+pub struct TextBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> TextBuffer<N> {
+    pub const fn new() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for TextBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::fmt::Write for TextBuffer<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(N - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n == s.len() { Ok(()) } else { Err(core::fmt::Error) }
+    }
+}
+
+/// Format `args` and write the text to `sink`
+///
+/// The text is formatted into an `N` byte buffer on the machine's stack before it is written, so
+/// each call can produce at most `N` bytes; if the text is longer, nothing is written and this
+/// returns an error. Usually called through [`write_text!`](crate::write_text).
+pub async fn write_text<const N: usize>(
+    sink: &mut impl ByteSink,
+    args: core::fmt::Arguments<'_>,
+) -> core::fmt::Result {
+    let mut buf = TextBuffer::<N>::new();
+    core::fmt::write(&mut buf, args)?;
+    sink.write(buf.as_bytes()).await;
+    Ok(())
+}
+
+/// Format text into a [`ByteSink`], like `write!`
+///
+/// This evaluates to a future, which must be awaited. Each call can produce up to 128 bytes,
+/// enough for e.g. an NMEA sentence or a line of a CSV log; call
+/// [`write_text`](crate::write_text()) directly to use a different limit.
+///
+/// ```
+/// use async_state_machine_example::{poll_once, write_text};
+/// use core::pin::pin;
+///
+/// let mut out = Vec::new();
+/// let result = poll_once(pin!(async {
+///     write_text!(&mut out, "{},{:.1}\n", 7, 21.54).await
+/// }));
+/// assert_eq!(result, Some(Ok(())));
+/// assert_eq!(out, b"7,21.5\n");
+/// ```
+#[macro_export]
+macro_rules! write_text {
+    ($sink:expr, $($arg:tt)*) => {
+        $crate::write_text::<128>($sink, format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll_once;
+    use core::pin::pin;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_yielding_sink() {
+        let value = (vec![1u16, 2], [3u8; 2]);
+        let mut out = Vec::new();
+        let mut polls = 1;
+        {
+            let mut sink = YieldingSink::new(|b| out.push(b));
+            let mut fut = pin!(async {
+                value.0.serialize(&mut sink).await;
+                value.1.serialize(&mut sink).await;
+            });
+            while poll_once(fut.as_mut()).is_none() {
+                polls += 1;
+            }
+        }
+        assert_eq!(out, [1, 0, 2, 0, 3, 3]);
+        assert_eq!(polls, 7);
+        assert_eq!(value.0.serialized_len() + value.1.serialized_len(), 6);
+    }
+
+    #[test]
+    fn test_text_buffer_overflow() {
+        use core::fmt::Write;
+
+        let mut buf = TextBuffer::<4>::new();
+        assert_eq!(write!(buf, "{}", 12), Ok(()));
+        assert!(write!(buf, "{}", 345).is_err());
+        assert_eq!(buf.as_bytes(), b"1234");
+
+        let mut out = Vec::new();
+        let result = poll_once(pin!(write_text::<4>(&mut out, format_args!("{}", 12345))));
+        assert!(result.unwrap().is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_frame_round_trip_in_random_chunks() {
+        let frames: Vec<Vec<u8>> = (0..20u8).map(|i| (0..i * 7).collect()).collect();
+        let mut stream = Vec::new();
+        for frame in &frames {
+            assert_eq!(poll_once(pin!(write_frame(frame, &mut stream))), Some(Ok(())));
+        }
+        let too_long = vec![0u8; usize::from(u16::MAX) + 1];
+        let result = poll_once(pin!(write_frame(&too_long, &mut stream)));
+        assert_eq!(result, Some(Err(FrameTooLong)));
+
+        let input = RefCell::new(VecDeque::new());
+        let mut output = Vec::new();
+        {
+            let mut fut = pin!(async {
+                let mut buf = Vec::new();
+                for _ in 0..frames.len() {
+                    read_frame(|| input.borrow_mut().pop_front(), &mut buf).await;
+                    output.push(buf.clone());
+                }
+            });
+            // Feed the stream in chunks of pseudo-random sizes from 0 to 15 bytes
+            let mut seed = 12345u32;
+            let mut rest = stream.as_slice();
+            while poll_once(fut.as_mut()).is_none() {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let (chunk, tail) = rest.split_at((seed >> 16) as usize % 16 % (rest.len() + 1));
+                input.borrow_mut().extend(chunk);
+                rest = tail;
+            }
+        }
+        assert_eq!(output, frames);
+    }
+}
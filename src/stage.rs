@@ -0,0 +1,219 @@
+//! Byte-in/byte-out processing stages, which sit between a serializer and its sink
+
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, rc::Rc};
+#[cfg(feature = "alloc")]
+use core::cell::Cell;
+#[cfg(feature = "alloc")]
+use core::pin::Pin;
+
+use crate::ByteSink;
+#[cfg(feature = "alloc")]
+use crate::{poll_once, AsyncSerialize, YieldingSink};
+
+/// A transformation applied to a byte stream, such as compression or encoding
+///
+/// A stage receives the stream in chunks of any size through [`Stage::write`], and writes its
+/// output to the sink it is given. It runs as part of whichever machine feeds it, yielding when
+/// that sink does, so its output can be drained in chunks like any other and its memory use is
+/// fixed by its own buffers.
+#[allow(async_fn_in_trait)]
+pub trait Stage {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink);
+
+    /// Write any buffered output, at the end of the stream
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        let _ = out;
+    }
+}
+
+/// A [`ByteSink`] which passes everything written to it through a [`Stage`] into `sink`
+///
+/// [`Staged::finish`] must be called once the stream is complete, to flush the stage.
+pub struct Staged<St: Stage, S: ByteSink> {
+    stage: St,
+    sink: S,
+}
+
+impl<St: Stage, S: ByteSink> Staged<St, S> {
+    pub fn new(stage: St, sink: S) -> Self {
+        Self { stage, sink }
+    }
+
+    pub async fn finish(&mut self) {
+        self.stage.finish(&mut self.sink).await;
+    }
+
+    pub fn into_parts(self) -> (St, S) {
+        (self.stage, self.sink)
+    }
+}
+
+impl<St: Stage, S: ByteSink> ByteSink for Staged<St, S> {
+    async fn write(&mut self, bytes: &[u8]) {
+        self.stage.write(bytes, &mut self.sink).await;
+    }
+}
+
+/// Pass `input` through `stage` in chunks of `chunk` bytes, for testing stages
+#[cfg(test)]
+pub(crate) fn run<St: Stage>(stage: St, input: &[u8], chunk: usize) -> (St, Vec<u8>) {
+    let mut staged = Staged::new(stage, Vec::new());
+    let done = crate::poll_once(core::pin::pin!(async {
+        for part in input.chunks(chunk) {
+            staged.write(part).await;
+        }
+        staged.finish().await;
+    }));
+    assert!(done.is_some());
+    staged.into_parts()
+}
+
+/// Passes the stream through unchanged, as the start of a [`Pipeline`]
+impl Stage for () {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        out.write(bytes).await;
+    }
+}
+
+/// Two stages applied one after the other, from [`Pipeline::then`]
+pub struct Chain<A: Stage, B: Stage> {
+    first: A,
+    second: B,
+}
+
+/// Feeds the first stage's output into the second stage
+struct Through<'a, B: Stage, S: ByteSink> {
+    stage: &'a mut B,
+    out: &'a mut S,
+}
+
+impl<B: Stage, S: ByteSink> ByteSink for Through<'_, B, S> {
+    async fn write(&mut self, bytes: &[u8]) {
+        self.stage.write(bytes, self.out).await;
+    }
+}
+
+impl<A: Stage, B: Stage> Stage for Chain<A, B> {
+    async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+        let mut through = Through { stage: &mut self.second, out };
+        self.first.write(bytes, &mut through).await;
+    }
+
+    async fn finish(&mut self, out: &mut impl ByteSink) {
+        let mut through = Through { stage: &mut self.second, out };
+        self.first.finish(&mut through).await;
+        self.second.finish(out).await;
+    }
+}
+
+/// A serialized value passed through a chain of stages
+///
+/// ```
+/// use async_state_machine_example::{Cobs, Compress, Crc, Pipeline};
+///
+/// let record = [7u8; 100];
+/// let mut reader = Pipeline::new(&record)
+///     .then(Compress::new())
+///     .then(Crc::new())
+///     .then(Cobs::new())
+///     .reader();
+/// let mut out = Vec::new();
+/// let mut buf = [0; 16];
+/// loop {
+///     let n = reader.read(&mut buf);
+///     out.extend_from_slice(&buf[..n]);
+///     if n < buf.len() {
+///         break;
+///     }
+/// }
+/// // The compressed record, its CRC, and the COBS overhead and terminator
+/// assert_eq!(out.len(), 8);
+/// assert_eq!(out.last(), Some(&0));
+/// ```
+#[cfg(feature = "alloc")]
+pub struct Pipeline<'a, T: AsyncSerialize + ?Sized, St: Stage> {
+    source: &'a T,
+    stages: St,
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, T: AsyncSerialize + ?Sized> Pipeline<'a, T, ()> {
+    pub fn new(source: &'a T) -> Self {
+        Self { source, stages: () }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, T: AsyncSerialize + ?Sized, St: Stage + 'a> Pipeline<'a, T, St> {
+    /// Add a stage, which receives the output of the stages before it
+    pub fn then<Next: Stage>(self, stage: Next) -> Pipeline<'a, T, Chain<St, Next>> {
+        Pipeline { source: self.source, stages: Chain { first: self.stages, second: stage } }
+    }
+
+    /// Create a machine which runs the pipeline, and from which its output can be read in chunks
+    pub fn reader(self) -> PipelineReader<impl Future<Output = ()> + 'a> {
+        let reg = Rc::new(Cell::new(None));
+        let out = reg.clone();
+        let fut = async move {
+            let sink = YieldingSink::new(move |b| out.set(Some(b)));
+            let mut staged = Staged::new(self.stages, sink);
+            self.source.serialize(&mut staged).await;
+            staged.finish().await;
+        };
+        PipelineReader { fut: Box::pin(fut), reg, finished: false }
+    }
+}
+
+/// Reads the output of a [`Pipeline`]
+#[cfg(feature = "alloc")]
+pub struct PipelineReader<F: Future<Output = ()>> {
+    fut: Pin<Box<F>>,
+    /// The byte written since the last poll, if any; stages may also yield without writing
+    reg: Rc<Cell<Option<u8>>>,
+    finished: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl<F: Future<Output = ()>> PipelineReader<F> {
+    /// Run the pipeline until `buf` is full or the output is complete
+    ///
+    /// Returns the number of bytes read, which is less than `buf.len()` only once the output is
+    /// complete.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut pos = 0;
+        while pos < buf.len() && !self.finished {
+            if poll_once(self.fut.as_mut()).is_some() {
+                self.finished = true;
+            } else if let Some(b) = self.reg.take() {
+                buf[pos] = b;
+                pos += 1;
+            }
+        }
+        pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::yield_now;
+
+    /// Yields before passing each chunk on, as a stage waiting on flow control would
+    struct Hesitant;
+
+    impl Stage for Hesitant {
+        async fn write(&mut self, bytes: &[u8], out: &mut impl ByteSink) {
+            yield_now().await;
+            out.write(bytes).await;
+        }
+    }
+
+    #[test]
+    fn test_reader_with_stage_yielding_without_output() {
+        let mut reader = Pipeline::new(&[1u8, 2, 3]).then(Hesitant).reader();
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf), 3);
+        assert_eq!(buf[..3], [1, 2, 3]);
+    }
+}
@@ -0,0 +1,83 @@
+//! Drift-free periodic ticks
+
+use core::time::Duration;
+
+use crate::periodic::next_deadline;
+use crate::{wait_until, Clock, Overrun};
+
+/// Completes once per period, on a fixed schedule
+///
+/// Tick times are fixed multiples of the period from the ticker's creation, so the schedule does
+/// not drift however late each tick is observed. The first tick is one period after creation.
+/// If ticks are missed entirely, e.g. because the loop body took longer than a period, the
+/// [`Overrun`] policy decides whether they are skipped or delivered back to back.
+///
+/// ```ignore
+/// let mut ticker = Ticker::every(Duration::from_millis(100), &clock);
+/// loop {
+///     ticker.next().await;
+///     data.sensor_data.store(Some(read_sensor()));
+/// }
+/// ```
+pub struct Ticker<C: Clock> {
+    clock: C,
+    period: Duration,
+    next_tick: Duration,
+    overrun: Overrun,
+}
+
+impl<C: Clock> Ticker<C> {
+    pub fn every(period: Duration, clock: C) -> Self {
+        let next_tick = clock.now() + period;
+        Self { clock, period, next_tick, overrun: Overrun::default() }
+    }
+
+    /// Set the policy for missed ticks
+    pub fn overrun(mut self, overrun: Overrun) -> Self {
+        self.overrun = overrun;
+        self
+    }
+
+    /// Wait for the next tick
+    pub async fn next(&mut self) {
+        wait_until(|| self.clock.now() >= self.next_tick).await;
+        self.next_tick = next_deadline(self.next_tick, self.period, self.clock.now(), self.overrun);
+    }
+}
+
+/// Create a [`Ticker`] which ticks every `period`
+pub fn interval<C: Clock>(period: Duration, clock: C) -> Ticker<C> {
+    Ticker::every(period, clock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{poll_once, MockClock};
+    use core::pin::pin;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_ticker_does_not_drift() {
+        let clock = MockClock::new();
+        let mut ticker = Ticker::every(10 * MS, &clock);
+
+        clock.advance(9 * MS);
+        assert_eq!(poll_once(pin!(ticker.next())), None);
+        // Observe the first tick late
+        clock.advance(4 * MS);
+        assert_eq!(poll_once(pin!(ticker.next())), Some(()));
+        // The next one is still due at 20ms
+        clock.advance(6 * MS);
+        assert_eq!(poll_once(pin!(ticker.next())), None);
+        clock.advance(MS);
+        assert_eq!(poll_once(pin!(ticker.next())), Some(()));
+        // Miss the ticks at 30 and 40ms
+        clock.advance(25 * MS);
+        assert_eq!(poll_once(pin!(ticker.next())), Some(()));
+        assert_eq!(poll_once(pin!(ticker.next())), None);
+        clock.advance(5 * MS);
+        assert_eq!(poll_once(pin!(ticker.next())), Some(()));
+    }
+}
@@ -0,0 +1,114 @@
+//! Time sources for timed state machines
+
+use core::cell::Cell;
+use core::time::Duration;
+
+/// A monotonic time source
+///
+/// Time is represented as the [`Duration`] elapsed since some arbitrary, fixed epoch (e.g. boot,
+/// or the creation of the clock). On embedded targets this is typically implemented on top of a
+/// free-running hardware timer.
+pub trait Clock {
+    /// Return the current time
+    fn now(&self) -> Duration;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+/// A [`Clock`] backed by [`std::time::Instant`], with its epoch at creation
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct StdClock {
+    epoch: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl StdClock {
+    pub fn new() -> Self {
+        Self { epoch: std::time::Instant::now() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+}
+
+/// A manually advanced [`Clock`], for tests and simulations
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Cell<Duration>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `dt`
+    pub fn advance(&self, dt: Duration) {
+        self.now.set(self.now.get() + dt);
+    }
+
+    /// Set the current time
+    pub fn set(&self, now: Duration) {
+        self.now.set(now);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// Error returned by [`timeout`] when the time limit elapses first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl core::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("timed out")
+    }
+}
+
+impl core::error::Error for TimedOut {}
+
+/// Run `fut`, giving up if it has not completed within `limit`
+///
+/// The limit is measured from the first poll. The inner future is polled before the clock is
+/// checked, so it always gets at least one chance to complete. If the limit elapses, `fut` is
+/// dropped and [`TimedOut`] is returned.
+pub async fn timeout<F: Future>(
+    limit: Duration,
+    clock: &impl Clock,
+    fut: F,
+) -> Result<F::Output, TimedOut> {
+    let start = clock.now();
+    let mut fut = core::pin::pin!(fut);
+    core::future::poll_fn(|cx| {
+        if let core::task::Poll::Ready(result) = fut.as_mut().poll(cx) {
+            core::task::Poll::Ready(Ok(result))
+        } else if clock.now() - start >= limit {
+            core::task::Poll::Ready(Err(TimedOut))
+        } else {
+            // The clock has to be checked again, whether or not the inner future wakes
+            cx.waker().wake_by_ref();
+            core::task::Poll::Pending
+        }
+    })
+    .await
+}
@@ -0,0 +1,108 @@
+//! A state machine wrapper for fallible futures
+
+use core::pin::Pin;
+
+use crate::AsyncStateMachine;
+
+/// Executes a future returning `Result<T, E>` one call at a time
+///
+/// This separates the success value from the error path: [`TryStateMachine::exec_ok`] only ever
+/// returns successful results, while an error is passed to the [`on_error`] hook and kept for
+/// inspection with [`TryStateMachine::error`]. Once the future has completed, either way, it is
+/// not polled again.
+///
+/// [`on_error`]: TryStateMachine::on_error
+pub struct TryStateMachine<'a, F, T, E, H = fn(&E)>
+where
+    F: Future<Output = Result<T, E>>,
+    H: FnMut(&E),
+{
+    inner: AsyncStateMachine<'a, F, Result<T, E>>,
+    on_error: H,
+    error: Option<E>,
+}
+
+impl<'a, F, T, E> TryStateMachine<'a, F, T, E>
+where
+    F: Future<Output = Result<T, E>>
+{
+    /// Create a new state machine from a provided pinned future
+    pub fn new(fut: Pin<&'a mut F>) -> Self {
+        fn ignore<E>(_: &E) {}
+        Self { inner: AsyncStateMachine::new(fut), on_error: ignore::<E>, error: None }
+    }
+}
+
+impl<'a, F, T, E, H> TryStateMachine<'a, F, T, E, H>
+where
+    F: Future<Output = Result<T, E>>,
+    H: FnMut(&E),
+{
+    /// Set a hook to be called with the error if the future fails, replacing any previous hook
+    pub fn on_error<G: FnMut(&E)>(self, hook: G) -> TryStateMachine<'a, F, T, E, G> {
+        TryStateMachine { inner: self.inner, on_error: hook, error: self.error }
+    }
+
+    /// Poll the future one time
+    ///
+    /// Returns `Some(T)` if the future completed successfully during this call. If it fails, the
+    /// error hook is called, the error is stored, and `None` is returned. Calls after completion
+    /// do nothing and return `None`.
+    pub fn exec_ok(&mut self) -> Option<T> {
+        match self.inner.exec()? {
+            Ok(value) => Some(value),
+            Err(e) => {
+                (self.on_error)(&e);
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    /// True once the future has completed, successfully or not
+    pub fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    /// True if the future completed with an error
+    pub fn is_failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// The error the future failed with, if any
+    pub fn error(&self) -> Option<&E> {
+        self.error.as_ref()
+    }
+
+    /// Take ownership of the error the future failed with, if any
+    pub fn take_error(&mut self) -> Option<E> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::pin::pin;
+    use futures::pending;
+
+    #[test]
+    fn test_error_path() {
+        let reported = Cell::new(None);
+        let fut = pin!(async {
+            pending!();
+            Err::<(), _>("no response")
+        });
+        let mut fsm = TryStateMachine::new(fut).on_error(|e| reported.set(Some(*e)));
+
+        assert_eq!(fsm.exec_ok(), None);
+        assert!(!fsm.is_finished());
+        assert_eq!(fsm.exec_ok(), None);
+        assert!(fsm.is_finished());
+        assert_eq!(fsm.error(), Some(&"no response"));
+        assert_eq!(reported.get(), Some("no response"));
+        // Stepping a finished machine is harmless
+        assert_eq!(fsm.exec_ok(), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! Helpers for waiting on conditions, such as hardware status flags
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use crate::{timeout, Clock, TimedOut};
+
+/// Future returned by [`yield_now`]
+struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            // Ask to be polled again, so the machine isn't reported as stalled
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Yield back to the caller exactly once, then continue
+///
+/// This is the recommended cooperative yield point for machines in this crate. Like
+/// `futures::pending!()`, it returns pending once; unlike `pending!()`, it also wakes the waker
+/// on the way out, so the machine reports that it wants to be polled again rather than showing
+/// up as [`Step::Stalled`](crate::Step::Stalled).
+///
+/// ```
+/// use async_state_machine_example::{yield_now, AsyncStateMachine, Step};
+/// use core::pin::pin;
+///
+/// let fut = pin!(async {
+///     yield_now().await;
+///     "done"
+/// });
+/// let mut fsm = AsyncStateMachine::new(fut);
+/// assert_eq!(fsm.exec_step(), Step::Pending);
+/// assert_eq!(fsm.exec_step(), Step::Ready("done"));
+/// ```
+pub fn yield_now() -> impl Future<Output = ()> {
+    YieldNow { yielded: false }
+}
+
+/// Yield back to the caller `n` times, completing on the following poll
+pub async fn yield_times(n: usize) {
+    for _ in 0..n {
+        yield_now().await
+    }
+}
+
+/// Complete on the `n`th poll
+///
+/// For systems where the tick rate is the time base, this is a delay of `n` ticks. A count of
+/// zero or one completes on the first poll.
+pub async fn delay_polls(n: usize) {
+    yield_times(n.saturating_sub(1)).await
+}
+
+/// Wait until `condition` returns true
+///
+/// The condition is checked once per poll, including on the first poll, so the future completes
+/// immediately if it is already true.
+pub async fn wait_until(mut condition: impl FnMut() -> bool) {
+    while !condition() {
+        yield_now().await
+    }
+}
+
+/// Wait for a busy indication to clear, giving up after `limit`
+///
+/// This is the usual structure for driving peripherals which report completion through a status
+/// bit, e.g. the write-in-progress flag of an SPI flash.
+pub async fn busy_wait(
+    mut is_busy: impl FnMut() -> bool,
+    limit: Duration,
+    clock: &impl Clock,
+) -> Result<(), TimedOut> {
+    timeout(limit, clock, wait_until(|| !is_busy())).await
+}
+
+/// Run a fallible async operation up to `attempts` times
+///
+/// `op` is called to create a fresh future for each attempt. The first success is returned, or
+/// the error from the final attempt if all of them fail. `attempts` of zero is treated as one.
+pub async fn retry<T, E, F>(attempts: usize, mut op: impl FnMut() -> F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let mut remaining = attempts.max(1);
+    loop {
+        remaining -= 1;
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if remaining == 0 => return Err(e),
+            Err(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{poll_once, MockClock};
+    use core::cell::Cell;
+    use core::pin::pin;
+
+    #[test]
+    fn test_poll_counts() {
+        let mut fut = pin!(yield_times(2));
+        assert_eq!(poll_once(fut.as_mut()), None);
+        assert_eq!(poll_once(fut.as_mut()), None);
+        assert_eq!(poll_once(fut.as_mut()), Some(()));
+
+        let mut fut = pin!(delay_polls(2));
+        assert_eq!(poll_once(fut.as_mut()), None);
+        assert_eq!(poll_once(fut.as_mut()), Some(()));
+        assert_eq!(poll_once(pin!(delay_polls(0))), Some(()));
+    }
+
+    #[test]
+    fn test_busy_wait() {
+        let clock = MockClock::new();
+        let busy = Cell::new(true);
+        let mut fut = pin!(busy_wait(|| busy.get(), Duration::from_millis(10), &clock));
+        assert_eq!(poll_once(fut.as_mut()), None);
+        busy.set(false);
+        assert_eq!(poll_once(fut.as_mut()), Some(Ok(())));
+
+        busy.set(true);
+        let mut fut = pin!(busy_wait(|| busy.get(), Duration::from_millis(10), &clock));
+        assert_eq!(poll_once(fut.as_mut()), None);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(poll_once(fut.as_mut()), Some(Err(TimedOut)));
+    }
+
+    #[test]
+    fn test_retry() {
+        let calls = Cell::new(0);
+        let op = || async {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 { Err(calls.get()) } else { Ok(()) }
+        };
+        assert_eq!(poll_once(pin!(retry(3, op))), Some(Ok(())));
+        calls.set(0);
+        assert_eq!(poll_once(pin!(retry(2, op))), Some(Err(2)));
+    }
+}
@@ -0,0 +1,147 @@
+//! Wakers which record how often they are woken
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+pub use counting::CountingWaker;
+
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+mod counting {
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::Waker;
+
+    struct WakeCounter {
+        count: AtomicUsize,
+    }
+
+    impl Wake for WakeCounter {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// A waker which counts the wakes it receives
+    ///
+    /// Nothing is scheduled when it is woken; the count just records whether the future asked to be
+    /// polled again, which is useful for diagnosing machines which are stuck waiting on something
+    /// that never wakes them.
+    pub struct CountingWaker {
+        counter: Arc<WakeCounter>,
+        waker: Waker,
+    }
+
+    impl CountingWaker {
+        pub fn new() -> Self {
+            let counter = Arc::new(WakeCounter { count: AtomicUsize::new(0) });
+            let waker = Waker::from(counter.clone());
+            Self { counter, waker }
+        }
+
+        /// The waker to poll with
+        pub fn waker(&self) -> &Waker {
+            &self.waker
+        }
+
+        /// The total number of times this waker, or any clone of it, has been woken
+        pub fn wake_count(&self) -> usize {
+            self.counter.count.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Default for CountingWaker {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+/// The waker an [`AsyncStateMachine`](crate::AsyncStateMachine) polls its future with
+///
+/// With `alloc`, this is a [`CountingWaker`], so wakes from clones stored by the future are
+/// counted too. Without it, there is nowhere for a clone to keep a count of its own which is
+/// guaranteed to outlive the machine, so the waker counts in place while it is lent to a poll,
+/// and its clones count in [`STORED_WAKES`], which is shared by every machine. A machine then
+/// also sees wakes meant for other machines, which can only make it report pending rather than
+/// stalled, never miss a wake of its own.
+pub(crate) struct MachineWaker {
+    #[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+    waker: CountingWaker,
+    #[cfg(not(all(feature = "alloc", target_has_atomic = "ptr")))]
+    count: core::sync::atomic::AtomicUsize,
+}
+
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+impl MachineWaker {
+    pub(crate) fn new() -> Self {
+        Self { waker: CountingWaker::new() }
+    }
+
+    pub(crate) fn wake_count(&self) -> usize {
+        self.waker.wake_count()
+    }
+
+    pub(crate) fn poll<F: Future + ?Sized>(&self, fut: Pin<&mut F>) -> Poll<F::Output> {
+        fut.poll(&mut Context::from_waker(self.waker.waker()))
+    }
+}
+
+/// Wakes through clones of every [`MachineWaker`], when clones can't count for their own machine
+#[cfg(not(all(feature = "alloc", target_has_atomic = "ptr")))]
+static STORED_WAKES: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(not(all(feature = "alloc", target_has_atomic = "ptr")))]
+impl MachineWaker {
+    pub(crate) fn new() -> Self {
+        Self { count: core::sync::atomic::AtomicUsize::new(0) }
+    }
+
+    pub(crate) fn wake_count(&self) -> usize {
+        use core::sync::atomic::Ordering;
+        let stored = STORED_WAKES.load(Ordering::Relaxed);
+        self.count.load(Ordering::Relaxed).wrapping_add(stored)
+    }
+
+    pub(crate) fn poll<F: Future + ?Sized>(&self, fut: Pin<&mut F>) -> Poll<F::Output> {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use core::task::{RawWaker, RawWakerVTable, Waker};
+
+        const STORED: RawWakerVTable = RawWakerVTable::new(clone, wake_stored, wake_stored, drop);
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &STORED)
+        }
+        fn wake_stored(_: *const ()) {
+            // Stored clones may be woken from an interrupt handler or another thread
+            #[cfg(target_has_atomic = "ptr")]
+            STORED_WAKES.fetch_add(1, Ordering::Relaxed);
+            #[cfg(not(target_has_atomic = "ptr"))]
+            critical_section::with(|_| {
+                let wakes = STORED_WAKES.load(Ordering::Relaxed);
+                STORED_WAKES.store(wakes.wrapping_add(1), Ordering::Relaxed);
+            });
+        }
+        fn wake_by_ref(count: *const ()) {
+            // SAFETY: Only the waker lent to the poll below has this vtable, so `count` points to
+            // the counter, which outlives the poll. Clones get the `STORED` vtable.
+            let count = unsafe { &*(count as *const AtomicUsize) };
+            // A plain load and store works on cores without atomic read-modify-write, and the
+            // waker is only woken from the polling context while it is lent
+            count.store(count.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+        }
+        fn drop(_: *const ()) {}
+        // Waking by value is only possible on clones, which have the `STORED` vtable
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, drop, wake_by_ref, drop);
+
+        let raw = RawWaker::new(&self.count as *const AtomicUsize as *const (), &VTABLE);
+        // SAFETY: The vtable functions uphold the `RawWaker` contract as described above, and the
+        // waker doesn't outlive `self`
+        let waker = unsafe { Waker::from_raw(raw) };
+        fut.poll(&mut Context::from_waker(&waker))
+    }
+}